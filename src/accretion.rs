@@ -0,0 +1,138 @@
+use crate::collisions::swept_contact;
+use crate::events::{EventKind, EventLog, SimulationEvent};
+use crate::objects::{CelestialObject, ObjectType};
+use crate::particles::ParticleStore;
+use crate::real::Real;
+
+// Black holes eat anything that comes too close
+pub struct AccretionEngine {
+    pub enabled: bool,
+    // Capture radius in event horizon radii. Slow infalling matter is lost inside
+    // 4GM/c², twice the horizon. The drawn size of the hole counts too, so the
+    // hole never appears to swallow less than it covers
+    pub capture_horizons: Real,
+    pub swallowed: u32, // objects swallowed so far
+}
+
+impl AccretionEngine {
+    pub fn new() -> Self {
+        AccretionEngine {
+            enabled: true,
+            capture_horizons: 2.0,
+            swallowed: 0,
+        }
+    }
+
+    pub fn capture_radius(&self, hole: &CelestialObject) -> Real {
+        hole.radius.max(self.capture_horizons * hole.event_horizon_radius)
+    }
+
+    // Let every black hole absorb the objects whose centres crossed its capture radius
+    // during the last step of length dt. Mass, momentum and centre of mass are kept,
+    // and every meal goes into the event log. Returns true if anything was swallowed
+    pub fn absorb(&mut self, particles: &mut ParticleStore, dt: Real, age: f64, events: &mut EventLog) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let holes: Vec<usize> = (0..particles.len())
+            .filter(|&i| particles.objects[i].object_type == ObjectType::BlackHole)
+            .collect();
+        if holes.is_empty() {
+            return false;
+        }
+
+        let mut eaten = vec![false; particles.len()];
+        for &h in &holes {
+            if eaten[h] {
+                continue;
+            }
+            for (i, eaten_i) in eaten.iter_mut().enumerate() {
+                if i == h || *eaten_i || !can_fall_in(particles, h, i) {
+                    continue;
+                }
+                let reach = self.capture_radius(&particles.objects[h]);
+                let (mut hole, meal) = (particles.particle(h), particles.particle(i));
+                if swept_contact(&hole, &meal, reach, dt).is_none() {
+                    continue;
+                }
+
+                let mass = hole.mass + meal.mass;
+                hole.x = (hole.x * hole.mass + meal.x * meal.mass) / mass;
+                hole.y = (hole.y * hole.mass + meal.y * meal.mass) / mass;
+                hole.z = (hole.z * hole.mass + meal.z * meal.mass) / mass;
+                hole.vx = (hole.vx * hole.mass + meal.vx * meal.mass) / mass;
+                hole.vy = (hole.vy * hole.mass + meal.vy * meal.mass) / mass;
+                hole.vz = (hole.vz * hole.mass + meal.vz * meal.mass) / mass;
+                hole.mass = mass;
+                particles.set_particle(h, hole);
+                particles.objects[h].update_event_horizon(mass);
+
+                events.push(SimulationEvent {
+                    age,
+                    x: meal.x,
+                    y: meal.y,
+                    z: meal.z,
+                    kind: EventKind::Accretion {
+                        swallowed: particles.objects[i].object_type,
+                        mass: meal.mass,
+                        hole_mass: mass,
+                    },
+                });
+                *eaten_i = true;
+                self.swallowed += 1;
+            }
+        }
+
+        if !eaten.contains(&true) {
+            return false;
+        }
+        particles.retain(|index| !eaten[index]);
+        true
+    }
+}
+
+impl Default for AccretionEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// The galactic bulge and dark energy aren't lumps that can fall in, and a hole
+// only eats things lighter than itself. Other black holes and neutron stars merge
+// with it instead (see gravitational_waves.rs)
+fn can_fall_in(particles: &ParticleStore, hole: usize, i: usize) -> bool {
+    let object_type = particles.objects[i].object_type;
+    !matches!(object_type, ObjectType::GalaxyCenter | ObjectType::DarkEnergy)
+        && !object_type.is_compact()
+        && particles.mass[i] < particles.mass[hole]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::particles::Particle;
+    use crate::test_support::{assert_kept, totals};
+
+    // A planet that flew through the hole's capture radius during the step ends up inside
+    // the hole, with nothing lost on the way
+    #[test]
+    fn swallowing_keeps_mass_momentum_and_centre_of_mass() {
+        let mut engine = AccretionEngine::new();
+        let mut events = EventLog::new();
+        let hole_mass = 1e6;
+        let mut particles: ParticleStore = [
+            (Particle::new(0.0, 0.0, 0.5, -0.2, hole_mass), CelestialObject::new(3.0, hole_mass, ObjectType::BlackHole, 0.0)),
+            (Particle::new(-4.0, 1.0, -60.0, 5.0, 200.0), CelestialObject::new(1.0, 200.0, ObjectType::Planet, 0.0)),
+        ].into_iter().collect();
+        particles.vz[1] = 2.0;
+        let before = totals(&particles);
+        let horizon = particles.objects[0].event_horizon_radius;
+
+        assert!(engine.absorb(&mut particles, 0.1, 0.0, &mut events));
+        assert_eq!((particles.len(), engine.swallowed), (1, 1));
+        assert_eq!(particles.objects[0].object_type, ObjectType::BlackHole);
+        assert_kept(before, totals(&particles));
+        assert!(particles.objects[0].event_horizon_radius > horizon, "the event horizon didn't grow");
+    }
+}
@@ -0,0 +1,134 @@
+use bigbang_simulator::particles::{Particle, ParticleStore};
+use bigbang_simulator::real::{to_f32, Real};
+
+// Where a point in the world ends up on screen
+#[derive(Debug, Clone, Copy)]
+pub struct Projected {
+    pub x: f32,
+    pub y: f32,
+    pub scale: f32, // screen pixels per world unit at that depth
+    pub depth: f32, // towards the viewer - bigger is nearer
+}
+
+// The view onto the universe. Flat scenes are seen straight down the z axis, panned and
+// zoomed. In 3D the camera also orbits the origin (yaw about z, then pitch about the
+// screen's x axis) and adds perspective: things nearer than the origin look bigger.
+// Zooming only scales the picture, so the perspective stays the same at every zoom
+pub struct Camera {
+    pub zoom: f32,
+    pub offset_x: f32, // pan, in screen pixels
+    pub offset_y: f32,
+    pub yaw: f32,   // radians
+    pub pitch: f32, // radians, 0 = looking straight down, pi/2 = edge-on with +z up
+    pub perspective: bool,
+    pub eye_distance: f32, // world units from the origin to the eye
+    screen_width: f32,
+    screen_height: f32,
+}
+
+impl Camera {
+    pub fn new(screen_width: f32, screen_height: f32) -> Self {
+        Camera {
+            zoom: 1.0,
+            offset_x: 0.0,
+            offset_y: 0.0,
+            yaw: 0.0,
+            pitch: 0.0,
+            perspective: false,
+            eye_distance: 2000.0,
+            screen_width,
+            screen_height,
+        }
+    }
+
+    pub fn resize(&mut self, screen_width: f32, screen_height: f32) {
+        self.screen_width = screen_width;
+        self.screen_height = screen_height;
+    }
+
+    // Flat top-down view, or a 3D one tipped over far enough to show the disk's thickness
+    pub fn set_perspective(&mut self, on: bool) {
+        self.perspective = on;
+        self.reset();
+    }
+
+    // Back to the starting view (R key)
+    pub fn reset(&mut self) {
+        self.zoom = 1.0;
+        self.offset_x = 0.0;
+        self.offset_y = 0.0;
+        self.yaw = 0.0;
+        self.pitch = if self.perspective { 1.0 } else { 0.0 };
+    }
+
+    // Turn the 3D view by a mouse drag of (dx, dy) pixels. Pitch stops at edge-on
+    pub fn orbit(&mut self, dx: f32, dy: f32) {
+        if !self.perspective {
+            return;
+        }
+        self.yaw += dx * 0.01;
+        self.pitch = (self.pitch + dy * 0.01).clamp(-std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_2);
+    }
+
+    // Zoom by `factor`, keeping whatever is under the screen point (x, y) in place
+    pub fn zoom_at(&mut self, x: f32, y: f32, factor: f32) {
+        let view_x = (x - self.screen_width / 2.0 - self.offset_x) / self.zoom;
+        let view_y = (y - self.screen_height / 2.0 - self.offset_y) / self.zoom;
+
+        let old_zoom = self.zoom;
+        self.zoom = (self.zoom * factor).clamp(0.05, 30.0); // Dont zoom too far!
+
+        self.offset_x += view_x * (old_zoom - self.zoom);
+        self.offset_y += view_y * (old_zoom - self.zoom);
+    }
+
+    // Screen position of a world point, or None if it's behind the eye
+    pub fn project(&self, x: Real, y: Real, z: Real) -> Option<Projected> {
+        let (x, y, z) = (to_f32(x), to_f32(y), to_f32(z));
+        let (view_x, view_y, depth, perspective) = if self.perspective {
+            let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+            let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+            let turned_x = x * cos_yaw - y * sin_yaw;
+            let turned_y = x * sin_yaw + y * cos_yaw;
+            let view_y = turned_y * cos_pitch - z * sin_pitch;
+            let depth = turned_y * sin_pitch + z * cos_pitch;
+
+            let distance = self.eye_distance - depth;
+            if distance < 1.0 {
+                return None;
+            }
+            (turned_x, view_y, depth, self.eye_distance / distance)
+        } else {
+            (x, y, z, 1.0)
+        };
+
+        let scale = self.zoom * perspective;
+        Some(Projected {
+            x: self.screen_width / 2.0 + self.offset_x + view_x * scale,
+            y: self.screen_height / 2.0 + self.offset_y + view_y * scale,
+            scale,
+            depth,
+        })
+    }
+
+    pub fn project_object(&self, body: &Particle) -> Option<Projected> {
+        self.project(body.x, body.y, body.z)
+    }
+
+    // The object drawn under the screen point (x, y), with 25 pixels of slack around it.
+    // When several overlap the nearest one wins
+    pub fn object_at(&self, particles: &ParticleStore, x: f32, y: f32) -> Option<usize> {
+        let mut best: Option<(usize, f32)> = None;
+        for (i, (body, obj)) in particles.iter().enumerate() {
+            let Some(point) = self.project_object(&body) else {
+                continue;
+            };
+            let (dx, dy) = (point.x - x, point.y - y);
+            let reach = to_f32(obj.radius) * point.scale + 25.0;
+            if dx * dx + dy * dy < reach * reach && best.is_none_or(|(_, depth)| point.depth > depth) {
+                best = Some((i, point.depth));
+            }
+        }
+        best.map(|(i, _)| i)
+    }
+}
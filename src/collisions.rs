@@ -0,0 +1,677 @@
+use std::collections::HashMap;
+use rand::Rng;
+use crate::gravity::G;
+use crate::objects::{CelestialObject, ObjectType};
+use crate::particles::{Body, Particle, ParticleStore};
+use crate::real::{consts, Real};
+
+// What happens when two bodies touch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CollisionMode {
+    Off,   // bodies pass through each other (gravity alone)
+    Merge, // perfectly inelastic: the pair becomes one body
+    Bounce, // pairs with a restitution coefficient bounce, everything else merges
+}
+
+impl CollisionMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CollisionMode::Off => "Off",
+            CollisionMode::Merge => "Merge",
+            CollisionMode::Bounce => "Bounce",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            CollisionMode::Off => CollisionMode::Merge,
+            CollisionMode::Merge => CollisionMode::Bounce,
+            CollisionMode::Bounce => CollisionMode::Off,
+        }
+    }
+}
+
+pub struct CollisionEngine {
+    pub mode: CollisionMode,
+    pub merges: u32,  // bodies swallowed so far
+    pub bounces: u32, // bounces so far
+    pub shatters: u32, // impacts that broke bodies into debris so far
+    // Giant impacts between planets, asteroids and comets can break them apart (X key).
+    // Off by default; the giant impact demo switches it on
+    pub fragmentation: bool,
+    // Impact energy per unit mass that strips half the mass off the pair,
+    // in units of the merged body's gravitational binding energy per unit mass
+    pub disruption_strength: Real,
+    // Impacts below this fraction of that energy only erode, so they merge or bounce as
+    // usual. At 0.2 the largest remnant would keep over 90% of the mass
+    pub erosion_threshold: Real,
+    pub min_fragment_mass: Real, // no debris lighter than this
+    pub max_fragments: usize,   // cap on debris per impact
+    // Coefficient of restitution for the type pairs that bounce in Bounce mode:
+    // 1 is perfectly elastic, 0 leaves the pair moving together after contact
+    restitution: HashMap<(ObjectType, ObjectType), Real>,
+}
+
+impl CollisionEngine {
+    pub fn new() -> Self {
+        let mut engine = CollisionEngine {
+            mode: CollisionMode::Off,
+            merges: 0,
+            bounces: 0,
+            shatters: 0,
+            fragmentation: false,
+            disruption_strength: 1.0,
+            erosion_threshold: 0.2,
+            min_fragment_mass: 0.5,
+            max_fragments: 24,
+            restitution: HashMap::new(),
+        };
+        
+        // Rock is fairly bouncy, icy comets soak up most of the impact,
+        // and anything hitting a planet mostly thuds into its surface
+        engine.set_restitution(ObjectType::Asteroid, ObjectType::Asteroid, 0.5);
+        engine.set_restitution(ObjectType::Asteroid, ObjectType::Comet, 0.3);
+        engine.set_restitution(ObjectType::Comet, ObjectType::Comet, 0.2);
+        engine.set_restitution(ObjectType::Asteroid, ObjectType::Planet, 0.3);
+        engine.set_restitution(ObjectType::Comet, ObjectType::Planet, 0.1);
+        engine
+    }
+    
+    // Make a and b bounce off each other in Bounce mode (in either order)
+    pub fn set_restitution(&mut self, a: ObjectType, b: ObjectType, coefficient: Real) {
+        let coefficient = coefficient.clamp(0.0, 1.0);
+        self.restitution.insert((a, b), coefficient);
+        self.restitution.insert((b, a), coefficient);
+    }
+    
+    // Restitution for a pair, or None if the pair merges instead
+    pub fn restitution(&self, a: ObjectType, b: ObjectType) -> Option<Real> {
+        self.restitution.get(&(a, b)).copied()
+    }
+
+    // Find the pairs that touched during the last step of length dt and shatter, bounce or
+    // merge them. Returns true if any object changed, so cached forces can be thrown away
+    pub fn resolve(&mut self, particles: &mut ParticleStore, dt: Real, age: f64) -> bool {
+        if self.mode == CollisionMode::Off {
+            return false;
+        }
+
+        let contacts = find_contacts(particles, dt);
+        if contacts.is_empty() {
+            return false;
+        }
+
+        // A body can touch several others in one step (or be swallowed and then hit),
+        // so every merge goes into whatever its partners have already become
+        let mut absorbed_into: Vec<usize> = (0..particles.len()).collect();
+        let mut debris = Vec::new();
+        for (i, j) in contacts {
+            let a = survivor(&mut absorbed_into, i);
+            let b = survivor(&mut absorbed_into, j);
+            if a == b {
+                continue;
+            }
+            
+            // The heavier body carries on in its own slot
+            let (keep, gone) = if particles.mass[a] >= particles.mass[b] { (a, b) } else { (b, a) };
+            
+            if self.fragmentation
+                && let Some(when) = contact_time(particles, keep, gone, dt)
+                && let Some(((remnant, object), fragments)) = self.shatter(particles, keep, gone, when, age)
+            {
+                particles.replace(keep, remnant, object);
+                absorbed_into[gone] = keep;
+                debris.extend(fragments);
+                self.shatters += 1;
+                continue;
+            }
+            
+            if self.mode == CollisionMode::Bounce
+                && let Some(coefficient) = self.restitution(particles.objects[a].object_type,
+                                                            particles.objects[b].object_type)
+            {
+                // An earlier bounce this step may have moved them apart already
+                if let Some(when) = contact_time(particles, a, b, dt) {
+                    bounce(particles, a, b, when, coefficient);
+                    self.bounces += 1;
+                }
+                continue;
+            }
+
+            let (merged, object) = merge(particles, keep, gone, age);
+            particles.replace(keep, merged, object);
+            absorbed_into[gone] = keep;
+            self.merges += 1;
+        }
+
+        particles.retain(|index| absorbed_into[index] == index);
+        particles.extend(debris);
+        true
+    }
+    
+    // Break up an impact that's too energetic for the pair to stay whole. Follows the
+    // Leinhardt & Stewart (2012) scaling: with Q the impact energy per unit of total mass
+    // (1/2 mu v² / M) and Q* the energy that disperses half the mass, the largest remnant
+    // keeps M (1 - Q / 2Q*) up to Q = 1.8 Q*, then falls off as a power law. The rest
+    // leaves as asteroid-sized debris. Returns None if the impact is below the erosion
+    // threshold or there wouldn't be enough debris for two fragments, so the pair just
+    // merges or bounces instead
+    fn shatter(&self, particles: &ParticleStore, keep: usize, gone: usize, when: Real, age: f64)
+               -> Option<(Body, Vec<Body>)> {
+        let (heavy_object, light_object) = (&particles.objects[keep], &particles.objects[gone]);
+        let breakable = |t: ObjectType| matches!(t, ObjectType::Planet | ObjectType::Asteroid | ObjectType::Comet);
+        if !breakable(heavy_object.object_type) || !breakable(light_object.object_type) {
+            return None;
+        }
+        let (heavy, light) = (particles.particle(keep), particles.particle(gone));
+        
+        let total = heavy.mass + light.mass;
+        let reduced = heavy.mass * light.mass / total;
+        let dvx = light.vx - heavy.vx;
+        let dvy = light.vy - heavy.vy;
+        let dvz = light.vz - heavy.vz;
+        let impact_energy = 0.5 * reduced * (dvx * dvx + dvy * dvy + dvz * dvz) / total;
+        
+        // Gravitational binding energy of a uniform sphere, 3 G M² / 5 R, per unit mass
+        let radius = (heavy_object.radius.powi(3) + light_object.radius.powi(3)).cbrt();
+        let disruption_energy = self.disruption_strength * 0.6 * G * total / radius;
+        
+        let q = impact_energy / disruption_energy;
+        if q < self.erosion_threshold {
+            return None;
+        }
+        let remnant_fraction = if q < 1.8 {
+            1.0 - 0.5 * q
+        } else {
+            0.1 * (q / 1.8).powf(-1.5)
+        };
+        let remnant_mass = total * remnant_fraction;
+        let debris_mass = total - remnant_mass;
+        
+        let count = ((debris_mass / self.min_fragment_mass) as usize).min(self.max_fragments);
+        if count < 2 {
+            return None;
+        }
+        let fragment_mass = debris_mass / count as Real;
+        
+        // Everything happens around the pair's centre of mass at the moment of contact
+        let cx = (heavy.mass * (heavy.x + heavy.vx * when) + light.mass * (light.x + light.vx * when)) / total;
+        let cy = (heavy.mass * (heavy.y + heavy.vy * when) + light.mass * (light.y + light.vy * when)) / total;
+        let cz = (heavy.mass * (heavy.z + heavy.vz * when) + light.mass * (light.z + light.vz * when)) / total;
+        let cvx = (heavy.mass * heavy.vx + light.mass * light.vx) / total;
+        let cvy = (heavy.mass * heavy.vy + light.mass * light.vy) / total;
+        let cvz = (heavy.mass * heavy.vz + light.mass * light.vz) / total;
+        
+        // Debris sprays out of the impact site, at about escape speed, and carries the
+        // impactor's sideways motion (that is where a Moon-forming disk gets its spin).
+        // n points at the impactor and t along its sideways motion, so the spray fans out
+        // in the plane of the impact
+        let nx0 = (light.x - heavy.x) + dvx * when;
+        let ny0 = (light.y - heavy.y) + dvy * when;
+        let nz0 = (light.z - heavy.z) + dvz * when;
+        let length = (nx0 * nx0 + ny0 * ny0 + nz0 * nz0).sqrt().max(Real::EPSILON);
+        let (nx, ny, nz) = (nx0 / length, ny0 / length, nz0 / length);
+        let (tx, ty, tz) = sideways(nx, ny, nz, dvx, dvy, dvz);
+        let shear = (dvx * tx + dvy * ty + dvz * tz) * heavy.mass / total;
+        
+        let remnant_radius = radius * remnant_fraction.cbrt();
+        let fragment_radius = radius * (fragment_mass / total).cbrt();
+        let escape_speed = (2.0 * G * total / radius).sqrt();
+        
+        let mut rng = rand::thread_rng();
+        let mut fragments = Vec::with_capacity(count);
+        let mut momentum = (0.0, 0.0, 0.0);
+        let mut moment = (0.0, 0.0, 0.0);
+        for k in 0..count {
+            // Fan over the hemisphere facing the impactor, staggered so pieces don't overlap
+            let angle = (k as Real + 0.5) / count as Real * consts::PI - consts::FRAC_PI_2
+                + rng.gen_range(-0.05..0.05);
+            let (sin, cos) = angle.sin_cos();
+            let (dx, dy, dz) = (nx * cos + tx * sin, ny * cos + ty * sin, nz * cos + tz * sin);
+            let distance = remnant_radius + fragment_radius * (1.5 + 2.5 * (k % 3) as Real);
+            let speed = escape_speed * rng.gen_range(0.6..1.1);
+            
+            let fragment = Particle {
+                x: cx + dx * distance,
+                y: cy + dy * distance,
+                z: cz + dz * distance,
+                vx: cvx + dx * speed + tx * shear,
+                vy: cvy + dy * speed + ty * shear,
+                vz: cvz + dz * speed + tz * shear,
+                mass: fragment_mass,
+                ..Particle::default()
+            };
+            momentum = (momentum.0 + fragment_mass * fragment.vx, momentum.1 + fragment_mass * fragment.vy,
+                        momentum.2 + fragment_mass * fragment.vz);
+            moment = (moment.0 + fragment_mass * fragment.x, moment.1 + fragment_mass * fragment.y,
+                      moment.2 + fragment_mass * fragment.z);
+            fragments.push((fragment, CelestialObject::new(fragment_radius, fragment_mass, ObjectType::Asteroid, age)));
+        }
+        
+        // The remnant takes whatever momentum (and centre of mass offset) the debris didn't,
+        // so both add up to the pair's
+        let (mut remnant, mut remnant_object) = merge(particles, keep, gone, age);
+        remnant.mass = remnant_mass;
+        remnant_object.radius = remnant_radius;
+        remnant.vx = (total * cvx - momentum.0) / remnant_mass;
+        remnant.vy = (total * cvy - momentum.1) / remnant_mass;
+        remnant.vz = (total * cvz - momentum.2) / remnant_mass;
+        remnant.x = (total * cx - moment.0) / remnant_mass - remnant.vx * when;
+        remnant.y = (total * cy - moment.1) / remnant_mass - remnant.vy * when;
+        remnant.z = (total * cz - moment.2) / remnant_mass - remnant.vz * when;
+        for (fragment, _) in &mut fragments {
+            fragment.x -= fragment.vx * when;
+            fragment.y -= fragment.vy * when;
+            fragment.z -= fragment.vz * when;
+        }
+        Some(((remnant, remnant_object), fragments))
+    }
+}
+
+impl Default for CollisionEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Unit vector at right angles to n, along the part of v that isn't along n. For a head-on v
+// any perpendicular will do; that one stays in the x-y plane when n does, so flat scenes
+// stay flat
+fn sideways(nx: Real, ny: Real, nz: Real, vx: Real, vy: Real, vz: Real) -> (Real, Real, Real) {
+    let along = vx * nx + vy * ny + vz * nz;
+    let (px, py, pz) = (vx - along * nx, vy - along * ny, vz - along * nz);
+    let length = (px * px + py * py + pz * pz).sqrt();
+    if length > Real::EPSILON {
+        return (px / length, py / length, pz / length);
+    }
+    let flat = (nx * nx + ny * ny).sqrt();
+    if flat > Real::EPSILON {
+        (-ny / flat, nx / flat, 0.0)
+    } else {
+        (1.0, 0.0, 0.0)
+    }
+}
+
+// Dark energy and the galactic bulge are diffuse, so nothing hits them
+fn is_solid(object_type: ObjectType) -> bool {
+    !matches!(object_type, ObjectType::DarkEnergy | ObjectType::GalaxyCenter)
+}
+
+// Pairs whose radii overlapped at some point during the last step, earliest contact first.
+// Each object moved in a straight line from (x - vx dt, y - vy dt, z - vz dt) to (x, y, z), so fast
+// movers are caught even if they jumped clean over each other between frames.
+// Broad phase is sweep and prune on the x extent of each object's path
+fn find_contacts(particles: &ParticleStore, dt: Real) -> Vec<(usize, usize)> {
+    let mut spans: Vec<(Real, Real, usize)> = (0..particles.len())
+        .filter(|&i| is_solid(particles.objects[i].object_type))
+        .map(|i| {
+            let (x, radius) = (particles.x[i], particles.objects[i].radius);
+            let start_x = x - particles.vx[i] * dt;
+            (start_x.min(x) - radius, start_x.max(x) + radius, i)
+        })
+        .collect();
+    spans.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let mut contacts = Vec::new();
+    for (n, &(_, end, i)) in spans.iter().enumerate() {
+        for &(other_start, _, j) in &spans[n + 1..] {
+            if other_start > end {
+                break;
+            }
+            if let Some(when) = contact_time(particles, i, j, dt) {
+                contacts.push((when, i.min(j), i.max(j)));
+            }
+        }
+    }
+
+    contacts.sort_by(|a, b| a.0.total_cmp(&b.0));
+    contacts.into_iter().map(|(_, i, j)| (i, j)).collect()
+}
+
+// Swept sphere test. The separation over the step is p + v s for s in [-dt, 0];
+// if its closest approach is within the sum of the radii, returns when the surfaces
+// first touched (-dt if they already overlapped at the start of the step)
+fn contact_time(particles: &ParticleStore, i: usize, j: usize, dt: Real) -> Option<Real> {
+    let reach = particles.objects[i].radius + particles.objects[j].radius;
+    swept_contact(&particles.particle(i), &particles.particle(j), reach, dt)
+}
+
+// The same test for any distance between the centres (a capture radius, say)
+pub fn swept_contact(a: &Particle, b: &Particle, reach: Real, dt: Real) -> Option<Real> {
+    let px = b.x - a.x;
+    let py = b.y - a.y;
+    let pz = b.z - a.z;
+    let vx = b.vx - a.vx;
+    let vy = b.vy - a.vy;
+    let vz = b.vz - a.vz;
+
+    let speed_sq = vx * vx + vy * vy + vz * vz;
+    let along = px * vx + py * vy + pz * vz;
+    let closest = if speed_sq > 0.0 {
+        (-along / speed_sq).clamp(-dt, 0.0)
+    } else {
+        0.0
+    };
+
+    let dx = px + vx * closest;
+    let dy = py + vy * closest;
+    let dz = pz + vz * closest;
+    if dx * dx + dy * dy + dz * dz >= reach * reach {
+        return None;
+    }
+    if speed_sq == 0.0 {
+        return Some(-dt);
+    }
+
+    // Earlier root of |p + v s|² = reach²
+    let discriminant = along * along - speed_sq * (px * px + py * py + pz * pz - reach * reach);
+    let touch = (-along - discriminant.max(0.0).sqrt()) / speed_sq;
+    Some(touch.clamp(-dt, closest))
+}
+
+// Bounce a pair that first touched at time `when` (<= 0) in the last step: rewind both
+// to the moment of contact, swap momentum along the line of centres, losing the
+// fraction set by the restitution coefficient, and let them fly apart for the rest
+// of the step. Tangential motion is untouched (no friction)
+fn bounce(particles: &mut ParticleStore, i: usize, j: usize, when: Real, restitution: Real) {
+    let (mut a, mut b) = (particles.particle(i), particles.particle(j));
+    for obj in [&mut a, &mut b] {
+        obj.x += obj.vx * when;
+        obj.y += obj.vy * when;
+        obj.z += obj.vz * when;
+    }
+
+    let dx = b.x - a.x;
+    let dy = b.y - a.y;
+    let dz = b.z - a.z;
+    let distance = (dx * dx + dy * dy + dz * dz).sqrt().max(Real::EPSILON);
+    let (nx, ny, nz) = (dx / distance, dy / distance, dz / distance);
+
+    // Only push them apart if they are still closing in
+    let closing = (b.vx - a.vx) * nx + (b.vy - a.vy) * ny + (b.vz - a.vz) * nz;
+    if closing < 0.0 {
+        let impulse = -(1.0 + restitution) * closing / (1.0 / a.mass + 1.0 / b.mass);
+        a.vx -= impulse / a.mass * nx;
+        a.vy -= impulse / a.mass * ny;
+        a.vz -= impulse / a.mass * nz;
+        b.vx += impulse / b.mass * nx;
+        b.vy += impulse / b.mass * ny;
+        b.vz += impulse / b.mass * nz;
+    }
+
+    for obj in [&mut a, &mut b] {
+        obj.x -= obj.vx * when;
+        obj.y -= obj.vy * when;
+        obj.z -= obj.vz * when;
+    }
+
+    // Bodies resting on each other (a rubble pile) can still overlap a little;
+    // separate them along the normal, the lighter one moving further
+    let dx = b.x - a.x;
+    let dy = b.y - a.y;
+    let dz = b.z - a.z;
+    let reach = particles.objects[i].radius + particles.objects[j].radius;
+    let overlap = reach - (dx * dx + dy * dy + dz * dz).sqrt();
+    if overlap > 0.0 {
+        let share = b.mass / (a.mass + b.mass);
+        a.x -= nx * overlap * share;
+        a.y -= ny * overlap * share;
+        a.z -= nz * overlap * share;
+        b.x += nx * overlap * (1.0 - share);
+        b.y += ny * overlap * (1.0 - share);
+        b.z += nz * overlap * (1.0 - share);
+    }
+    particles.set_particle(i, a);
+    particles.set_particle(j, b);
+}
+
+// Follow the chain of merges to the body that holds i now
+fn survivor(absorbed_into: &mut [usize], mut i: usize) -> usize {
+    while absorbed_into[i] != i {
+        absorbed_into[i] = absorbed_into[absorbed_into[i]];
+        i = absorbed_into[i];
+    }
+    i
+}
+
+// What a pair turns into. The heavier partner usually wins, with a few exceptions
+// for compact objects
+fn merged_type(a: ObjectType, b: ObjectType) -> ObjectType {
+    use ObjectType::*;
+    let neutron = |t: ObjectType| matches!(t, NeutronStar | Pulsar);
+
+    match (a, b) {
+        // Nothing gets out of a black hole
+        (BlackHole, _) | (_, BlackHole) => BlackHole,
+        // Two neutron stars are over the mass limit, and the remnant collapses
+        (x, y) if neutron(x) && neutron(y) => BlackHole,
+        // Two white dwarfs past the Chandrasekhar mass collapse to a neutron star
+        (WhiteDwarf, WhiteDwarf) => NeutronStar,
+        // A neutron star sinking into a star makes a Thorne-Zytkow object,
+        // which looks like an ordinary (if bloated) star from outside
+        (Star, x) | (x, Star) if neutron(x) => Star,
+        // A white dwarf inside a star's envelope is just a stellar core
+        (Star, WhiteDwarf) | (WhiteDwarf, Star) => Star,
+        // Otherwise the more compact / more massive kind survives
+        (x, y) => if rank(x) >= rank(y) { x } else { y },
+    }
+}
+
+// Which kind of body absorbs which, from small rocks up to stellar remnants
+fn rank(object_type: ObjectType) -> u8 {
+    match object_type {
+        ObjectType::Dust => 0,
+        ObjectType::Comet => 1,
+        ObjectType::Asteroid => 2,
+        ObjectType::Planet => 3,
+        ObjectType::Star => 4,
+        ObjectType::WhiteDwarf => 5,
+        ObjectType::NeutronStar | ObjectType::Pulsar => 6,
+        ObjectType::BlackHole => 7,
+        ObjectType::DarkEnergy | ObjectType::GalaxyCenter => 8,
+    }
+}
+
+// Perfectly inelastic merger: masses add, the pair's momentum and centre of mass carry over.
+// `keep` is the index of the more massive partner, which keeps its look if the type stays the same
+pub fn merge(particles: &ParticleStore, keep: usize, gone: usize, age: f64) -> Body {
+    let (heavy_object, light_object) = (&particles.objects[keep], &particles.objects[gone]);
+    let (heavy, light) = (particles.particle(keep), particles.particle(gone));
+    let mass = heavy.mass + light.mass;
+    let weigh = |a: Real, b: Real| (a * heavy.mass + b * light.mass) / mass;
+
+    let object_type = merged_type(heavy_object.object_type, light_object.object_type);
+
+    // Ordinary matter keeps its density, so volumes add.
+    // Compact remnants stay the size of the bigger one
+    let radius = match object_type {
+        ObjectType::BlackHole | ObjectType::NeutronStar | ObjectType::Pulsar | ObjectType::WhiteDwarf =>
+            heavy_object.radius.max(light_object.radius),
+        _ => (heavy_object.radius.powi(3) + light_object.radius.powi(3)).cbrt(),
+    };
+
+    let mut merged = if object_type == heavy_object.object_type {
+        let mut grown = heavy_object.clone();
+        grown.luminosity *= mass / heavy.mass;
+        grown
+    } else {
+        CelestialObject::new(radius, mass, object_type, age)
+    };
+    merged.radius = radius;
+    merged.update_event_horizon(mass);
+
+    let particle = Particle {
+        x: weigh(heavy.x, light.x),
+        y: weigh(heavy.y, light.y),
+        z: weigh(heavy.z, light.z),
+        vx: weigh(heavy.vx, light.vx),
+        vy: weigh(heavy.vy, light.vy),
+        vz: weigh(heavy.vz, light.vz),
+        ax: weigh(heavy.ax, light.ax),
+        ay: weigh(heavy.ay, light.ay),
+        az: weigh(heavy.az, light.az),
+        mass,
+    };
+    (particle, merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::real::to_f64;
+
+    fn body(x: Real, vx: Real, vy: Real, mass: Real, object_type: ObjectType) -> Body {
+        (Particle::new(x, 0.0, vx, vy, mass), CelestialObject::new(2.0, mass, object_type, 0.0))
+    }
+
+    // Total mass and momentum, in f64 so the sums don't add round-off of their own
+    fn totals(particles: &ParticleStore) -> (f64, [f64; 3]) {
+        let mut mass = 0.0;
+        let mut momentum = [0.0; 3];
+        for (obj, _) in particles.iter() {
+            let m = to_f64(obj.mass);
+            mass += m;
+            momentum[0] += m * to_f64(obj.vx);
+            momentum[1] += m * to_f64(obj.vy);
+            momentum[2] += m * to_f64(obj.vz);
+        }
+        (mass, momentum)
+    }
+
+    fn assert_conserved(before: (f64, [f64; 3]), after: (f64, [f64; 3])) {
+        let scale = before.1.iter().map(|p| p.abs()).sum::<f64>().max(1.0);
+        assert!((after.0 - before.0).abs() < 1e-5 * before.0, "mass {} became {}", before.0, after.0);
+        for axis in 0..3 {
+            assert!((after.1[axis] - before.1[axis]).abs() < 1e-5 * scale,
+                    "momentum {:?} became {:?}", before.1, after.1);
+        }
+    }
+
+    fn kinetic_energy(particles: &ParticleStore) -> f64 {
+        particles.iter().map(|(obj, _)| {
+            let (vx, vy, vz) = (to_f64(obj.vx), to_f64(obj.vy), to_f64(obj.vz));
+            0.5 * to_f64(obj.mass) * (vx * vx + vy * vy + vz * vz)
+        }).sum()
+    }
+
+    // A head-on bounce keeps the fraction e of the closing speed, so it loses
+    // (1 - e²) of the kinetic energy of the relative motion, ½ μ v² with μ = m₁m₂/(m₁+m₂)
+    #[test]
+    fn bouncing_keeps_momentum_and_loses_energy_by_restitution() {
+        for restitution in [0.0, 0.3, 0.7, 1.0] {
+            let mut engine = CollisionEngine::new();
+            engine.mode = CollisionMode::Bounce;
+            engine.set_restitution(ObjectType::Asteroid, ObjectType::Asteroid, restitution);
+            let (m1, m2, closing) = (4.0, 1.0, 40.0);
+            let mut particles: ParticleStore = [
+                body(-1.0, 15.0, 0.0, m1, ObjectType::Asteroid),
+                body(1.0, 15.0 - closing, 0.0, m2, ObjectType::Asteroid),
+            ].into_iter().collect();
+            let before = totals(&particles);
+            let energy = kinetic_energy(&particles);
+
+            assert!(engine.resolve(&mut particles, 0.1, 0.0));
+            assert_eq!((particles.len(), engine.bounces), (2, 1));
+            assert_conserved(before, totals(&particles));
+
+            let reduced = to_f64(m1 * m2 / (m1 + m2));
+            let lost = 0.5 * reduced * to_f64(closing * closing) * (1.0 - to_f64(restitution).powi(2));
+            let measured = energy - kinetic_energy(&particles);
+            assert!((measured - lost).abs() < 1e-4 * energy, "e = {restitution}: lost {measured}, expected {lost}");
+            let separating = to_f64(particles.vx[1] - particles.vx[0]);
+            assert!((separating - to_f64(restitution * closing)).abs() < 1e-4 * to_f64(closing),
+                    "e = {restitution}: separating at {separating}");
+        }
+    }
+
+    // Two small asteroids 60 units apart at the end of a step, closing at 1000 per unit
+    // time: they went clean through each other during it, touching first at s = -0.064
+    fn fast_pair(miss_by: Real) -> ParticleStore {
+        let mut particles: ParticleStore = [
+            body(30.0, 500.0, 0.0, 1.0, ObjectType::Asteroid),
+            body(-30.0, -500.0, 0.0, 1.0, ObjectType::Asteroid),
+        ].into_iter().collect();
+        particles.y[1] = miss_by;
+        particles
+    }
+
+    // The swept test catches the pass, and resolve merges or bounces the pair even though
+    // their end-of-step positions are far apart
+    #[test]
+    fn fast_movers_that_passed_through_each_other_are_caught() {
+        let dt = 0.1;
+        let particles = fast_pair(0.0);
+        let reach = particles.objects[0].radius + particles.objects[1].radius;
+        let when = swept_contact(&particles.particle(0), &particles.particle(1), reach, dt).expect("no contact");
+        assert!((-dt..=0.0).contains(&when), "contact at {when}");
+        assert!((when + 0.064).abs() < 1e-4, "contact at {when}, expected -0.064");
+
+        let mut engine = CollisionEngine::new();
+        engine.mode = CollisionMode::Merge;
+        let mut merging = particles.clone();
+        let before = totals(&merging);
+        assert!(engine.resolve(&mut merging, dt, 0.0));
+        assert_eq!((merging.len(), engine.merges), (1, 1));
+        assert_conserved(before, totals(&merging));
+
+        engine.mode = CollisionMode::Bounce;
+        engine.set_restitution(ObjectType::Asteroid, ObjectType::Asteroid, 1.0);
+        let mut bouncing = particles;
+        assert!(engine.resolve(&mut bouncing, dt, 0.0));
+        assert_eq!(engine.bounces, 1);
+        // Rewound to the contact and sent back the way they came, so they never crossed
+        assert!(bouncing.vx[0] < 0.0 && bouncing.vx[1] > 0.0, "({}, {})", bouncing.vx[0], bouncing.vx[1]);
+        assert!((bouncing.x[0] + 34.0).abs() < 1e-2 && (bouncing.x[1] - 34.0).abs() < 1e-2,
+                "ended at {} and {}", bouncing.x[0], bouncing.x[1]);
+    }
+
+    // Passing a little more than the sum of the radii apart is no contact at all
+    #[test]
+    fn fast_near_miss_is_not_a_contact() {
+        let dt = 0.1;
+        let mut particles = fast_pair(4.5);
+        let reach = particles.objects[0].radius + particles.objects[1].radius;
+        assert!(reach < 4.5);
+        assert_eq!(swept_contact(&particles.particle(0), &particles.particle(1), reach, dt), None);
+
+        let mut engine = CollisionEngine::new();
+        engine.mode = CollisionMode::Merge;
+        assert!(!engine.resolve(&mut particles, dt, 0.0));
+        assert_eq!(particles.len(), 2);
+    }
+
+    #[test]
+    fn merging_keeps_mass_and_momentum() {
+        let mut engine = CollisionEngine::new();
+        engine.mode = CollisionMode::Merge;
+        let mut particles: ParticleStore = [
+            body(-1.0, 3.0, 0.5, 10.0, ObjectType::Planet),
+            body(1.0, -4.0, 1.0, 3.0, ObjectType::Asteroid),
+        ].into_iter().collect();
+        particles.vz[1] = 0.25;
+        let before = totals(&particles);
+
+        assert!(engine.resolve(&mut particles, 0.1, 0.0));
+        assert_eq!(particles.len(), 1);
+        assert_eq!(particles.objects[0].object_type, ObjectType::Planet);
+        assert_conserved(before, totals(&particles));
+    }
+
+    #[test]
+    fn fragmenting_keeps_mass_and_momentum() {
+        let mut engine = CollisionEngine::new();
+        engine.mode = CollisionMode::Merge;
+        engine.fragmentation = true;
+        let mut particles: ParticleStore = [
+            body(-1.0, 40.0, 5.0, 10.0, ObjectType::Planet),
+            body(1.0, -60.0, -2.0, 8.0, ObjectType::Planet),
+        ].into_iter().collect();
+        particles.vz[0] = -3.0;
+        let before = totals(&particles);
+
+        assert!(engine.resolve(&mut particles, 0.1, 0.0));
+        assert_eq!(engine.shatters, 1);
+        assert!(particles.len() > 2, "only {} bodies after the impact", particles.len());
+        assert_conserved(before, totals(&particles));
+    }
+}
@@ -0,0 +1,198 @@
+use crate::particles::ParticleStore;
+use crate::physics::PhysicsEngine;
+use crate::real::{to_f64, Real};
+
+// The quantities an isolated system should keep, summed in f64 so the bookkeeping
+// itself doesn't add drift
+#[derive(Debug, Clone, Copy)]
+pub struct Conserved {
+    pub mass: f64,
+    pub kinetic: f64,
+    pub potential: f64,
+    pub momentum: (f64, f64, f64),
+    pub angular_momentum: (f64, f64, f64), // about the origin; only z is non-zero in a flat scene
+    pub centre_of_mass: (f64, f64, f64),
+    // Size scales, to turn absolute drifts into relative ones when the totals
+    // themselves are near zero (a system at rest has no net momentum to compare with)
+    momentum_scale: f64,        // sum of m |v|
+    angular_momentum_scale: f64, // sum of m |r x v|
+    radius: f64,                // rms distance from the centre of mass
+}
+
+impl Conserved {
+    // Potential energy comes from the active gravity solver (GravityEngine::potential_energy),
+    // over the same softened pair potential the force comes from (-G m1 m2 / sqrt(r² + eps²)
+    // for Plummer), so measuring costs about one more force evaluation. On top of that, each
+    // object's energy in the dark matter halos and in any force fields that have a potential.
+    // The engine is borrowed mutably only to let those fields look at the bodies first
+    pub fn measure(particles: &ParticleStore, physics: &mut PhysicsEngine) -> Self {
+        physics.prepare_force_fields(particles);
+        let gravity = &physics.gravity_engine;
+        let mut mass = 0.0;
+        let mut kinetic = 0.0;
+        let mut momentum = (0.0, 0.0, 0.0);
+        let mut angular_momentum = (0.0, 0.0, 0.0);
+        let mut weighted = (0.0, 0.0, 0.0);
+        let mut momentum_scale = 0.0;
+        let mut angular_momentum_scale = 0.0;
+        for (obj, _) in particles.iter() {
+            let m = to_f64(obj.mass);
+            let (x, y, z) = (to_f64(obj.x), to_f64(obj.y), to_f64(obj.z));
+            let (vx, vy, vz) = (to_f64(obj.vx), to_f64(obj.vy), to_f64(obj.vz));
+            // r x v
+            let arm = (y * vz - z * vy, z * vx - x * vz, x * vy - y * vx);
+            let speed_sq = vx * vx + vy * vy + vz * vz;
+            mass += m;
+            kinetic += 0.5 * m * speed_sq;
+            momentum = (momentum.0 + m * vx, momentum.1 + m * vy, momentum.2 + m * vz);
+            angular_momentum = (angular_momentum.0 + m * arm.0, angular_momentum.1 + m * arm.1,
+                                angular_momentum.2 + m * arm.2);
+            weighted = (weighted.0 + m * x, weighted.1 + m * y, weighted.2 + m * z);
+            momentum_scale += m * speed_sq.sqrt();
+            angular_momentum_scale += m * length(arm);
+        }
+
+        let centre_of_mass = if mass > 0.0 {
+            (weighted.0 / mass, weighted.1 / mass, weighted.2 / mass)
+        } else {
+            (0.0, 0.0, 0.0)
+        };
+        let radius = if mass > 0.0 {
+            let spread: f64 = particles.iter().map(|(obj, _)| {
+                let dx = to_f64(obj.x) - centre_of_mass.0;
+                let dy = to_f64(obj.y) - centre_of_mass.1;
+                let dz = to_f64(obj.z) - centre_of_mass.2;
+                to_f64(obj.mass) * (dx * dx + dy * dy + dz * dz)
+            }).sum();
+            (spread / mass).sqrt()
+        } else {
+            0.0
+        };
+
+        let mut potential = gravity.potential_energy(particles);
+
+        for (i, (obj, _)) in particles.iter().enumerate() {
+            let (x, y, z) = (to_f64(obj.x), to_f64(obj.y), to_f64(obj.z));
+            for halo in &gravity.halos {
+                potential += to_f64(obj.mass) * halo.potential_at(x, y, z);
+            }
+            for field in &physics.force_fields {
+                potential += to_f64(obj.mass) * field.potential_of(i, particles);
+            }
+        }
+
+        Conserved {
+            mass,
+            kinetic,
+            potential,
+            momentum,
+            angular_momentum,
+            centre_of_mass,
+            momentum_scale,
+            angular_momentum_scale,
+            radius,
+        }
+    }
+
+    pub fn energy(&self) -> f64 {
+        self.kinetic + self.potential
+    }
+}
+
+fn length(v: (f64, f64, f64)) -> f64 {
+    (v.0 * v.0 + v.1 * v.1 + v.2 * v.2).sqrt()
+}
+
+// Relative change of each quantity since the baseline. 0 is perfect
+#[derive(Debug, Clone, Copy)]
+pub struct Drift {
+    pub energy: f64,           // |E - E0| / |E0|
+    pub momentum: f64,         // |P - P0| / sum of m |v| at the start
+    pub angular_momentum: f64, // |L - L0| / sum of m |r x v| at the start
+    // How far the centre of mass moved from where the starting momentum would have
+    // carried it, over the starting rms radius
+    pub centre_of_mass: f64,
+    pub mass: f64,             // |M - M0| / M0 - mergers radiate, the boundary removes
+}
+
+// Tracks the conserved quantities and how far they've wandered since t = 0.
+// The main way to judge whether a softening, time step or speed limit can be trusted:
+// anything else (collisions, accretion, expansion, the boundary, the speed cap) shows
+// up here as drift too
+pub struct Diagnostics {
+    pub baseline: Option<Conserved>,
+    pub current: Option<Conserved>,
+    pub elapsed: f64, // simulated time since the baseline, in the integrator's units
+    pub worst_energy_drift: f64, // over every step since the baseline
+    // Times the velocity policy stepped in (once per object per step). Each one breaks
+    // conservation: a clamp throws energy and momentum away outright
+    pub speed_limited: u32,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Diagnostics {
+            baseline: None,
+            current: None,
+            elapsed: 0.0,
+            worst_energy_drift: 0.0,
+            speed_limited: 0,
+        }
+    }
+
+    // Start over with the current state as t = 0 (after loading a new scene, say)
+    pub fn reset(&mut self, particles: &ParticleStore, physics: &mut PhysicsEngine) {
+        *self = Diagnostics::new();
+        self.sample(particles, physics);
+    }
+
+    // Account for a step of length dt, and measure the state after it
+    pub fn record(&mut self, particles: &ParticleStore, physics: &mut PhysicsEngine, dt: Real) {
+        self.elapsed += to_f64(dt);
+        self.speed_limited += physics.speed_limited;
+        self.sample(particles, physics);
+    }
+
+    // Measure the state now. The first measurement becomes the baseline
+    fn sample(&mut self, particles: &ParticleStore, physics: &mut PhysicsEngine) {
+        let now = Conserved::measure(particles, physics);
+        self.baseline.get_or_insert(now);
+        self.current = Some(now);
+        if let Some(drift) = self.drift() {
+            self.worst_energy_drift = self.worst_energy_drift.max(drift.energy);
+        }
+    }
+
+    pub fn drift(&self) -> Option<Drift> {
+        let (start, now) = (self.baseline?, self.current?);
+        let relative = |change: f64, scale: f64| if scale > 0.0 { change / scale } else { 0.0 };
+
+        let change = |a: (f64, f64, f64), b: (f64, f64, f64)| length((a.0 - b.0, a.1 - b.1, a.2 - b.2));
+
+        // Where the centre of mass should be if it kept coasting
+        let expected = if start.mass > 0.0 {
+            let coast = self.elapsed / start.mass;
+            (start.centre_of_mass.0 + start.momentum.0 * coast,
+             start.centre_of_mass.1 + start.momentum.1 * coast,
+             start.centre_of_mass.2 + start.momentum.2 * coast)
+        } else {
+            start.centre_of_mass
+        };
+        let shift = change(now.centre_of_mass, expected);
+
+        Some(Drift {
+            energy: relative((now.energy() - start.energy()).abs(), start.energy().abs()),
+            momentum: relative(change(now.momentum, start.momentum), start.momentum_scale),
+            angular_momentum: relative(change(now.angular_momentum, start.angular_momentum),
+                                       start.angular_momentum_scale),
+            centre_of_mass: relative(shift, start.radius),
+            mass: relative((now.mass - start.mass).abs(), start.mass),
+        })
+    }
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
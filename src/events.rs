@@ -0,0 +1,96 @@
+use std::collections::VecDeque;
+use crate::objects::ObjectType;
+use crate::units;
+use crate::real::Real;
+
+// Something worth telling the user about, stamped with the universe age it happened at
+#[derive(Debug, Clone)]
+pub struct SimulationEvent {
+    pub age: f64,
+    pub x: Real,
+    pub y: Real,
+    pub z: Real,
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone)]
+pub enum EventKind {
+    // A black hole swallowed an object
+    Accretion { swallowed: ObjectType, mass: Real, hole_mass: Real },
+    // A star or planet was torn apart inside the Roche limit of a heavier body
+    TidalDisruption { victim: ObjectType, mass: Real, disruptor: ObjectType },
+    // Two black holes / neutron stars spiralled together
+    CompactMerger { first: ObjectType, second: ObjectType, remnant: ObjectType, mass: Real },
+    // Two black holes coalesced, radiating part of their mass and kicking the remnant
+    BlackHoleMerger { mass: Real, radiated: Real, kick: Real },
+    // The velocity clamp slowed objects down (the fastest of them, and how many in all)
+    SpeedClamped { object: ObjectType, speed: Real, limit: Real, count: u32 },
+}
+
+impl SimulationEvent {
+    pub fn description(&self) -> String {
+        match &self.kind {
+            EventKind::Accretion { swallowed, mass, hole_mass } => {
+                format!("{} fell into a black hole ({}, hole now {})", swallowed.name(),
+                        units::format_mass(*mass), units::format_mass(*hole_mass))
+            }
+            EventKind::TidalDisruption { victim, mass, disruptor } => {
+                format!("{} ({}) torn apart by a {}", victim.name(), units::format_mass(*mass), disruptor.name())
+            }
+            EventKind::CompactMerger { first, second, remnant, mass } => {
+                format!("{} + {} merged into a {} ({})", first.name(), second.name(), remnant.name(),
+                        units::format_mass(*mass))
+            }
+            EventKind::BlackHoleMerger { mass, radiated, kick } => {
+                format!("Black holes merged ({}, {:.1}% radiated, kick {})", units::format_mass(*mass),
+                        radiated * 100.0, units::format_speed(*kick))
+            }
+            EventKind::SpeedClamped { object, speed, limit, count } => {
+                format!("{} clamped from {} to {} ({} object{} this step)", object.name(),
+                        units::format_speed(*speed), units::format_speed(*limit), count,
+                        if *count == 1 { "" } else { "s" })
+            }
+        }
+    }
+}
+
+// The most recent events, oldest first. Old ones fall off the front
+pub struct EventLog {
+    events: VecDeque<SimulationEvent>,
+    capacity: usize,
+    pub total: u32, // events ever logged
+}
+
+impl EventLog {
+    pub fn new() -> Self {
+        EventLog {
+            events: VecDeque::new(),
+            capacity: 50,
+            total: 0,
+        }
+    }
+
+    pub fn push(&mut self, event: SimulationEvent) {
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+        self.total += 1;
+    }
+
+    // Newest first
+    pub fn recent(&self, count: usize) -> impl Iterator<Item = &SimulationEvent> {
+        self.events.iter().rev().take(count)
+    }
+
+    // Events no older than `window` in universe age
+    pub fn since(&self, age: f64, window: f64) -> impl Iterator<Item = &SimulationEvent> {
+        self.events.iter().filter(move |event| age - event.age <= window)
+    }
+}
+
+impl Default for EventLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
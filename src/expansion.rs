@@ -1,8 +1,9 @@
-use crate::objects::CelestialObject;
+use crate::particles::ParticleStore;
+use crate::real::Real;
 
 pub struct ExpansionEngine {
-    pub expansion_rate: f32,
-    pub dark_energy_density: f32,
+    pub expansion_rate: Real,
+    pub dark_energy_density: Real,
 }
 
 impl ExpansionEngine {
@@ -13,22 +14,34 @@ impl ExpansionEngine {
         }
     }
     
-    pub fn apply_expansion(&self, objects: &mut [CelestialObject], speed_factor: f32, age: f32) {
+    // Returns true if anything moved
+    pub fn apply_expansion(&self, particles: &mut ParticleStore, speed_factor: Real, _age: Real) -> bool {
         let current_expansion_rate = self.expansion_rate * speed_factor * 0.0001;
         
         if current_expansion_rate < 1e-10 {
-            return;
+            return false;
         }
         
         // Only affect objects really far from center
-        for obj in objects {
-            let distance_from_center = (obj.x * obj.x + obj.y * obj.y).sqrt();
+        let mut moved = false;
+        for i in 0..particles.len() {
+            let (x, y, z) = (particles.x[i], particles.y[i], particles.z[i]);
+            let distance_from_center = (x * x + y * y + z * z).sqrt();
             
             if distance_from_center > 1000.0 {
                 let expansion_factor = 1.0 + current_expansion_rate;
-                obj.x *= expansion_factor;
-                obj.y *= expansion_factor;
+                particles.x[i] *= expansion_factor;
+                particles.y[i] *= expansion_factor;
+                particles.z[i] *= expansion_factor;
+                moved = true;
             }
         }
+        moved
     }
-}
\ No newline at end of file
+}
+
+impl Default for ExpansionEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
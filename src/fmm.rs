@@ -0,0 +1,517 @@
+use crate::gravity::{GravityEngine, G};
+use crate::particles::ParticleStore;
+use crate::real::{to_f64, Real};
+
+// Average number of bodies per leaf the grid depth aims for. A 3D leaf has 26 neighbours
+// and 189 cells in its interaction list, against 8 and 27 in the plane, so it's made
+// bigger to keep the expansion work from swamping the direct sums
+const LEAF_SIZE: usize = 16;
+const LEAF_SIZE_3D: usize = 64;
+// Deepest level - 4^8 leaves (8^5 in 3D) is plenty and keeps the expansion arrays small
+const MAX_LEVEL: usize = 8;
+const MAX_LEVEL_3D: usize = 5;
+
+// Fast multipole method on a uniform quadtree, or octree as soon as anything is off the
+// z = 0 plane (like the Barnes-Hut tree). Every cell carries a multipole expansion
+// (moments of the bodies inside it) and a local expansion (Taylor series of the pull of
+// everything well separated from it). Far cells talk expansion-to-expansion, near cells
+// body-to-body, so the cost is O(n) and the error falls like (size / distance)^(order + 1).
+//
+// Expansions are Cartesian Taylor series in (x, y), or (x, y, z), up to total degree
+// `order`, of the same softened 1/r potential the other solvers use, so a flat scene gets
+// the 3D 1/r² force in the plane. Only Plummer softening reaches the far field; the other
+// kernels are plain 1/r out there
+pub struct FmmTree {
+    series: Series,
+    level: usize, // leaf level
+    three_d: bool,
+    origin: [f64; 3],
+    width: f64, // width of the root cell
+    leaf_of: Vec<usize>,     // leaf index of every body
+    leaf_bodies: Vec<usize>, // body indices grouped by leaf
+    leaf_start: Vec<usize>,  // leaf i owns leaf_bodies[leaf_start[i]..leaf_start[i + 1]]
+    locals: Vec<f64>,        // local expansions of the leaves, `series.len()` values each
+}
+
+impl FmmTree {
+    pub fn build(particles: &ParticleStore, order: usize, softening_length: Real) -> Self {
+        let mut min = [f64::MAX; 3];
+        let mut max = [f64::MIN; 3];
+        for (axis, values) in [&particles.x, &particles.y, &particles.z].into_iter().enumerate() {
+            for &v in values {
+                min[axis] = min[axis].min(to_f64(v));
+                max[axis] = max[axis].max(to_f64(v));
+            }
+        }
+        if particles.is_empty() {
+            (min, max) = ([0.0; 3], [1.0, 1.0, 0.0]);
+        }
+        let three_d = max[2] > min[2];
+        if !three_d {
+            // Flat: every body has the same z, and the cells keep their centres at it
+            let z = min[2];
+            (min[2], max[2]) = (z, z);
+        }
+        let width = (max[0] - min[0]).max(max[1] - min[1]).max(max[2] - min[2]).max(1.0) * 1.001;
+
+        let series = Series::new(order, three_d);
+        let terms = series.len();
+
+        // Deep enough for about LEAF_SIZE bodies per leaf, but at least level 2 so
+        // there are well separated cells at all
+        let (leaf_size, max_level) = if three_d { (LEAF_SIZE_3D, MAX_LEVEL_3D) } else { (LEAF_SIZE, MAX_LEVEL) };
+        let dims = if three_d { 3 } else { 2 };
+        let mut level = 2;
+        while level < max_level && (1usize << (dims * level)) * leaf_size < particles.len() {
+            level += 1;
+        }
+
+        let mut tree = FmmTree {
+            series,
+            level,
+            three_d,
+            origin: min,
+            width,
+            leaf_of: Vec::with_capacity(particles.len()),
+            leaf_bodies: Vec::new(),
+            leaf_start: Vec::new(),
+            locals: Vec::new(),
+        };
+
+        // Bucket the bodies by leaf (counting sort)
+        let leaf_count = tree.cells_at(level);
+        let mut counts = vec![0usize; leaf_count + 1];
+        for i in 0..particles.len() {
+            let leaf = tree.leaf_containing([to_f64(particles.x[i]), to_f64(particles.y[i]), to_f64(particles.z[i])]);
+            tree.leaf_of.push(leaf);
+            counts[leaf + 1] += 1;
+        }
+        for i in 0..leaf_count {
+            counts[i + 1] += counts[i];
+        }
+        tree.leaf_start = counts.clone();
+        tree.leaf_bodies = vec![0; particles.len()];
+        for (body, &leaf) in tree.leaf_of.iter().enumerate() {
+            tree.leaf_bodies[counts[leaf]] = body;
+            counts[leaf] += 1;
+        }
+
+        // Upward pass: multipoles of the leaves, then merged into their parents
+        let mut multipoles: Vec<Vec<f64>> = (0..=level).map(|l| vec![0.0; tree.cells_at(l) * terms]).collect();
+        for leaf in 0..leaf_count {
+            let center = tree.cell_center(level, leaf);
+            let expansion = &mut multipoles[level][leaf * terms..(leaf + 1) * terms];
+            for &body in tree.bodies_in(leaf) {
+                // Moments of -(body - centre), which keeps the shift formulas sign free
+                let powers = tree.series.powers([center[0] - to_f64(particles.x[body]), center[1] - to_f64(particles.y[body]),
+                                                 center[2] - to_f64(particles.z[body])]);
+                for (m, p) in expansion.iter_mut().zip(powers) {
+                    *m += to_f64(particles.mass[body]) * p;
+                }
+            }
+        }
+        for l in (0..level).rev() {
+            let (parents, children) = multipoles.split_at_mut(l + 1);
+            let (parents, children) = (&mut parents[l], &children[0]);
+            for child in 0..tree.cells_at(l + 1) {
+                let child_expansion = &children[child * terms..(child + 1) * terms];
+                if child_expansion[0] == 0.0 {
+                    continue; // no mass
+                }
+                let parent = tree.parent_of(l + 1, child);
+                let c = tree.cell_center(l + 1, child);
+                let p = tree.cell_center(l, parent);
+                tree.series.shift_multipole(
+                    child_expansion,
+                    &mut parents[parent * terms..(parent + 1) * terms],
+                    [p[0] - c[0], p[1] - c[1], p[2] - c[2]],
+                );
+            }
+        }
+
+        // Downward pass: every cell collects the multipoles of its interaction list (children
+        // of the parent's neighbours that aren't neighbours themselves), plus its parent's local
+        let softening_sq = to_f64(softening_length).powi(2);
+        let z_steps = if three_d { 7 } else { 1 };
+        let mut parent_locals: Vec<f64> = vec![0.0; terms];
+        for (l, level_multipoles) in multipoles.iter().enumerate().skip(2) {
+            let side = 1usize << l;
+            let cell_width = width / side as f64;
+
+            // On a uniform grid the source-to-target offset is one of 7 x 7 (x 7) cell steps,
+            // so the kernel's Taylor coefficients only have to be worked out once per step
+            let kernels: Vec<Vec<f64>> = (0..49 * z_steps).map(|k| {
+                let (ox, oy, oz) = ((k / z_steps / 7) as f64 - 3.0, (k / z_steps % 7) as f64 - 3.0,
+                                    if three_d { (k % 7) as f64 - 3.0 } else { 0.0 });
+                tree.series.kernel_taylor([ox * cell_width, oy * cell_width, oz * cell_width], softening_sq)
+            }).collect();
+
+            let mut locals = vec![0.0; tree.cells_at(l) * terms];
+            for cell in 0..tree.cells_at(l) {
+                let [ix, iy, iz] = tree.cell_coordinates(l, cell);
+                let target = &mut locals[cell * terms..(cell + 1) * terms];
+
+                if l > 2 {
+                    // Inherit the parent's local expansion, re-centred
+                    let parent = tree.parent_of(l, cell);
+                    let c = tree.cell_center(l, cell);
+                    let p = tree.cell_center(l - 1, parent);
+                    tree.series.shift_local(
+                        &parent_locals[parent * terms..(parent + 1) * terms],
+                        target,
+                        [c[0] - p[0], c[1] - p[1], c[2] - p[2]],
+                    );
+                }
+
+                let parent_side = side / 2;
+                let z_side = if three_d { parent_side } else { 1 };
+                let around = |i: usize, limit: usize| i.saturating_sub(1)..=(i + 1).min(limit - 1);
+                for qz in around(iz / 2, z_side) {
+                    for qy in around(iy / 2, parent_side) {
+                        for qx in around(ix / 2, parent_side) {
+                            let z_children = if three_d { 2 * qz..2 * qz + 2 } else { 0..1 };
+                            for sz in z_children {
+                                for sy in 2 * qy..2 * qy + 2 {
+                                    for sx in 2 * qx..2 * qx + 2 {
+                                        let ox = ix as i64 - sx as i64;
+                                        let oy = iy as i64 - sy as i64;
+                                        let oz = iz as i64 - sz as i64;
+                                        if ox.abs() <= 1 && oy.abs() <= 1 && oz.abs() <= 1 {
+                                            continue; // neighbour - handled body by body
+                                        }
+                                        let source_cell = tree.cell_index(l, [sx, sy, sz]);
+                                        let source = &level_multipoles[source_cell * terms..(source_cell + 1) * terms];
+                                        if source[0] == 0.0 {
+                                            continue;
+                                        }
+                                        let offset = ((ox + 3) * 7 + (oy + 3)) as usize * z_steps
+                                            + if three_d { (oz + 3) as usize } else { 0 };
+                                        tree.series.multipole_to_local(source, &kernels[offset], target);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            parent_locals = locals;
+        }
+        tree.locals = parent_locals;
+
+        tree
+    }
+
+    // Local expansion for the far field plus direct sums over the neighbouring leaves
+    pub fn acceleration_on(&self, index: usize, particles: &ParticleStore,
+                           engine: &GravityEngine) -> (Real, Real, Real) {
+        let (x, y, z) = (particles.x[index], particles.y[index], particles.z[index]);
+        let body = &particles.objects[index];
+        let leaf = self.leaf_of[index];
+        let terms = self.series.len();
+
+        let center = self.cell_center(self.level, leaf);
+        let local = &self.locals[leaf * terms..(leaf + 1) * terms];
+        let [gx, gy, gz] = self.series.gradient(local, [to_f64(x) - center[0], to_f64(y) - center[1],
+                                                        to_f64(z) - center[2]]);
+        let mut ax = (to_f64(G) * gx) as Real;
+        let mut ay = (to_f64(G) * gy) as Real;
+        let mut az = (to_f64(G) * gz) as Real;
+
+        let side = 1usize << self.level;
+        let z_side = if self.three_d { side } else { 1 };
+        let [ix, iy, iz] = self.cell_coordinates(self.level, leaf);
+        let around = |i: usize, limit: usize| i.saturating_sub(1)..=(i + 1).min(limit - 1);
+        for nz in around(iz, z_side) {
+            for ny in around(iy, side) {
+                for nx in around(ix, side) {
+                    for &j in self.bodies_in(self.cell_index(self.level, [nx, ny, nz])) {
+                        if j == index {
+                            continue;
+                        }
+                        let dx = particles.x[j] - x;
+                        let dy = particles.y[j] - y;
+                        let dz = particles.z[j] - z;
+                        let factor = engine.pair_factor(dx, dy, dz, body, &particles.objects[j]) * particles.mass[j];
+                        ax += factor * dx;
+                        ay += factor * dy;
+                        az += factor * dz;
+                    }
+                }
+            }
+        }
+
+        (ax, ay, az)
+    }
+
+    // Potential per unit mass at the body, the same way: the local expansion itself (the
+    // pull is its gradient, so the potential is minus it) plus the neighbouring leaves'
+    // pairs. In f64 for the energy bookkeeping
+    pub fn potential_on(&self, index: usize, particles: &ParticleStore, engine: &GravityEngine) -> f64 {
+        let (x, y, z) = (to_f64(particles.x[index]), to_f64(particles.y[index]), to_f64(particles.z[index]));
+        let body = &particles.objects[index];
+        let leaf = self.leaf_of[index];
+        let terms = self.series.len();
+
+        let center = self.cell_center(self.level, leaf);
+        let local = &self.locals[leaf * terms..(leaf + 1) * terms];
+        let mut potential = -to_f64(G) * self.series.value(local, [x - center[0], y - center[1], z - center[2]]);
+
+        let side = 1usize << self.level;
+        let z_side = if self.three_d { side } else { 1 };
+        let [ix, iy, iz] = self.cell_coordinates(self.level, leaf);
+        let around = |i: usize, limit: usize| i.saturating_sub(1)..=(i + 1).min(limit - 1);
+        for nz in around(iz, z_side) {
+            for ny in around(iy, side) {
+                for nx in around(ix, side) {
+                    for &j in self.bodies_in(self.cell_index(self.level, [nx, ny, nz])) {
+                        if j == index {
+                            continue;
+                        }
+                        let dx = to_f64(particles.x[j]) - x;
+                        let dy = to_f64(particles.y[j]) - y;
+                        let dz = to_f64(particles.z[j]) - z;
+                        let length = to_f64(engine.softening_of(body) + engine.softening_of(&particles.objects[j]));
+                        potential += to_f64(G) * to_f64(particles.mass[j])
+                            * engine.softening_kernel.potential((dx * dx + dy * dy + dz * dz).sqrt(), length);
+                    }
+                }
+            }
+        }
+
+        potential
+    }
+
+    fn bodies_in(&self, leaf: usize) -> &[usize] {
+        &self.leaf_bodies[self.leaf_start[leaf]..self.leaf_start[leaf + 1]]
+    }
+
+    // Cells are numbered x fastest, then y, then z. A flat tree has one layer of cells
+    fn cells_at(&self, level: usize) -> usize {
+        let side = 1usize << level;
+        if self.three_d { side * side * side } else { side * side }
+    }
+
+    fn cell_index(&self, level: usize, [ix, iy, iz]: [usize; 3]) -> usize {
+        let side = 1usize << level;
+        (iz * side + iy) * side + ix
+    }
+
+    fn cell_coordinates(&self, level: usize, cell: usize) -> [usize; 3] {
+        let side = 1usize << level;
+        [cell % side, cell / side % side, cell / (side * side)]
+    }
+
+    fn parent_of(&self, level: usize, cell: usize) -> usize {
+        let [ix, iy, iz] = self.cell_coordinates(level, cell);
+        self.cell_index(level - 1, [ix / 2, iy / 2, iz / 2])
+    }
+
+    fn leaf_containing(&self, position: [f64; 3]) -> usize {
+        let side = 1usize << self.level;
+        let cell = |axis: usize| (((position[axis] - self.origin[axis]) / self.width * side as f64) as usize).min(side - 1);
+        let iz = if self.three_d { cell(2) } else { 0 };
+        self.cell_index(self.level, [cell(0), cell(1), iz])
+    }
+
+    fn cell_center(&self, level: usize, cell: usize) -> [f64; 3] {
+        let side = 1usize << level;
+        let cell_width = self.width / side as f64;
+        let [ix, iy, iz] = self.cell_coordinates(level, cell);
+        let center = |axis: usize, i: usize| self.origin[axis] + (i as f64 + 0.5) * cell_width;
+        [center(0, ix), center(1, iy), if self.three_d { center(2, iz) } else { self.origin[2] }]
+    }
+}
+
+// Bookkeeping for polynomials in (x, y), or (x, y, z), truncated at total degree `order`.
+// Coefficients are stored per monomial x^a y^b z^c, ordered by degree. Flat trees only
+// get the monomials without z, so they cost what a two-variable series would
+struct Series {
+    terms: Vec<[usize; 3]>,
+    index: Vec<Vec<Vec<usize>>>, // index[a][b][c] -> position in `terms` (only a + b + c <= order)
+    // Every (k, j) with |k| + |j| <= order, as (k, j, k + j, binomial(k + j, k))
+    m2l_pairs: Vec<(usize, usize, usize, f64)>,
+    binomial: Vec<Vec<f64>>,
+    three_d: bool,
+}
+
+impl Series {
+    fn new(order: usize, three_d: bool) -> Self {
+        let mut terms = Vec::new();
+        let mut index = vec![vec![vec![usize::MAX; order + 1]; order + 1]; order + 1];
+        for degree in 0..=order {
+            for a in (0..=degree).rev() {
+                for b in (0..=degree - a).rev() {
+                    let c = degree - a - b;
+                    if c > 0 && !three_d {
+                        continue;
+                    }
+                    index[a][b][c] = terms.len();
+                    terms.push([a, b, c]);
+                }
+            }
+        }
+
+        let mut binomial = vec![vec![0.0; order + 1]; order + 1];
+        for n in 0..=order {
+            binomial[n][0] = 1.0;
+            for k in 1..=n {
+                binomial[n][k] = binomial[n - 1][k - 1] + if k < n { binomial[n - 1][k] } else { 0.0 };
+            }
+        }
+
+        let mut m2l_pairs = Vec::new();
+        for (k, &[ka, kb, kc]) in terms.iter().enumerate() {
+            for (j, &[ja, jb, jc]) in terms.iter().enumerate() {
+                if ka + kb + kc + ja + jb + jc <= order {
+                    let n = index[ka + ja][kb + jb][kc + jc];
+                    let factor = binomial[ka + ja][ka] * binomial[kb + jb][kb] * binomial[kc + jc][kc];
+                    m2l_pairs.push((k, j, n, factor));
+                }
+            }
+        }
+
+        Series { terms, index, m2l_pairs, binomial, three_d }
+    }
+
+    fn len(&self) -> usize {
+        self.terms.len()
+    }
+
+    fn order(&self) -> usize {
+        self.index.len() - 1
+    }
+
+    fn at(&self, [a, b, c]: [usize; 3]) -> usize {
+        self.index[a][b][c]
+    }
+
+    // x^a y^b z^c for every monomial
+    fn powers(&self, [x, y, z]: [f64; 3]) -> Vec<f64> {
+        let order = self.order();
+        let mut px = vec![1.0; order + 1];
+        let mut py = vec![1.0; order + 1];
+        let mut pz = vec![1.0; order + 1];
+        for i in 1..=order {
+            px[i] = px[i - 1] * x;
+            py[i] = py[i - 1] * y;
+            pz[i] = pz[i - 1] * z;
+        }
+        self.terms.iter().map(|&[a, b, c]| px[a] * py[b] * pz[c]).collect()
+    }
+
+    fn multiply(&self, p: &[f64], q: &[f64]) -> Vec<f64> {
+        let order = self.order();
+        let mut out = vec![0.0; self.len()];
+        for (i, &[a1, b1, c1]) in self.terms.iter().enumerate() {
+            if p[i] == 0.0 {
+                continue;
+            }
+            for (j, &[a2, b2, c2]) in self.terms.iter().enumerate() {
+                if a1 + a2 + b1 + b2 + c1 + c2 <= order {
+                    out[self.at([a1 + a2, b1 + b2, c1 + c2])] += p[i] * q[j];
+                }
+            }
+        }
+        out
+    }
+
+    // Taylor coefficients of h -> 1 / sqrt(|R + h|² + eps²) around h = 0. Writing
+    // s = |R|² + eps² and u = (2 R.h + |h|²) / s, the kernel is s^(-1/2) (1 + u)^(-1/2),
+    // and the binomial series in u ends at power `order` because u has no constant term
+    fn kernel_taylor(&self, r: [f64; 3], softening_sq: f64) -> Vec<f64> {
+        let s = r[0] * r[0] + r[1] * r[1] + r[2] * r[2] + softening_sq;
+        let order = self.order();
+        let axes = if self.three_d { 3 } else { 2 };
+
+        let mut u = vec![0.0; self.len()];
+        for axis in 0..axes {
+            let mut unit = [0; 3];
+            unit[axis] = 1;
+            if order >= 1 {
+                u[self.at(unit)] = 2.0 * r[axis] / s;
+            }
+            unit[axis] = 2;
+            if order >= 2 {
+                u[self.at(unit)] = 1.0 / s;
+            }
+        }
+
+        let mut result = vec![0.0; self.len()];
+        let mut u_power = vec![0.0; self.len()];
+        u_power[0] = 1.0;
+        let mut coefficient = 1.0; // binomial(-1/2, k)
+        for k in 0..=order {
+            for (r, p) in result.iter_mut().zip(&u_power) {
+                *r += coefficient * p;
+            }
+            coefficient *= (-0.5 - k as f64) / (k as f64 + 1.0);
+            u_power = self.multiply(&u_power, &u);
+        }
+
+        let scale = 1.0 / s.sqrt();
+        result.iter_mut().for_each(|r| *r *= scale);
+        result
+    }
+
+    // Move a multipole to a centre `d` away: M'_j = sum_i C(j, i) M_i d^(j - i)
+    fn shift_multipole(&self, source: &[f64], target: &mut [f64], d: [f64; 3]) {
+        let powers = self.powers(d);
+        for (j, &[ja, jb, jc]) in self.terms.iter().enumerate() {
+            for ia in 0..=ja {
+                for ib in 0..=jb {
+                    for ic in 0..=jc {
+                        let factor = self.binomial[ja][ia] * self.binomial[jb][ib] * self.binomial[jc][ic];
+                        target[j] += factor * source[self.at([ia, ib, ic])] * powers[self.at([ja - ia, jb - ib, jc - ic])];
+                    }
+                }
+            }
+        }
+    }
+
+    // Re-centre a local expansion `d` away: L'_i = sum_(k >= i) C(k, i) L_k d^(k - i)
+    fn shift_local(&self, source: &[f64], target: &mut [f64], d: [f64; 3]) {
+        let order = self.order();
+        let powers = self.powers(d);
+        for (i, &[ia, ib, ic]) in self.terms.iter().enumerate() {
+            for ka in ia..=order {
+                for kb in ib..=order - ka {
+                    let top_c = if self.three_d { order - ka - kb } else { 0 };
+                    for kc in ic..=top_c {
+                        let factor = self.binomial[ka][ia] * self.binomial[kb][ib] * self.binomial[kc][ic];
+                        target[i] += factor * source[self.at([ka, kb, kc])] * powers[self.at([ka - ia, kb - ib, kc - ic])];
+                    }
+                }
+            }
+        }
+    }
+
+    // L_k += sum_j C(k + j, k) K_(k + j) M_j
+    fn multipole_to_local(&self, multipole: &[f64], kernel: &[f64], local: &mut [f64]) {
+        for &(k, j, n, factor) in &self.m2l_pairs {
+            local[k] += factor * kernel[n] * multipole[j];
+        }
+    }
+
+    // sum_k L_k x^a y^b z^c at (x, y, z)
+    fn value(&self, local: &[f64], position: [f64; 3]) -> f64 {
+        local.iter().zip(self.powers(position)).map(|(l, p)| l * p).sum()
+    }
+
+    // Gradient of sum_k L_k x^a y^b z^c at (x, y, z)
+    fn gradient(&self, local: &[f64], position: [f64; 3]) -> [f64; 3] {
+        let powers = self.powers(position);
+        let mut g = [0.0; 3];
+        for (k, &[a, b, c]) in self.terms.iter().enumerate() {
+            if a > 0 {
+                g[0] += a as f64 * local[k] * powers[self.at([a - 1, b, c])];
+            }
+            if b > 0 {
+                g[1] += b as f64 * local[k] * powers[self.at([a, b - 1, c])];
+            }
+            if c > 0 {
+                g[2] += c as f64 * local[k] * powers[self.at([a, b, c - 1])];
+            }
+        }
+        g
+    }
+}
@@ -0,0 +1,144 @@
+use crate::halo::DarkMatterHalo;
+use crate::objects::CelestialObject;
+use crate::particles::{Particle, ParticleStore};
+use crate::real::{to_f64, Real};
+
+// An extra force on top of gravity. Register one with `PhysicsEngine::add_force_field` and
+// it's added in every force evaluation, after gravity, so every integrator sees it at
+// every stage. Everything is per unit mass: return accelerations, not forces. Each method
+// gets object i and the whole store, so a field can depend on where other bodies are.
+// Fields are shared between threads with the `parallel` feature, so they're Send + Sync
+pub trait ForceField: Send + Sync {
+    fn name(&self) -> &str;
+
+    // Called before every step and before the diagnostics ask for potentials: whenever the
+    // bodies may have been added, removed or changed type since the last call. Within a
+    // step they only move, so a field that looks at other bodies can pick them out here
+    // once instead of scanning the store per object
+    fn prepare(&mut self, _particles: &ParticleStore) {}
+
+    // Acceleration this field gives object i, from its position, velocity, mass or type
+    fn acceleration_on(&self, i: usize, particles: &ParticleStore) -> (Real, Real, Real);
+
+    // Rate of change of that acceleration, for the Hermite integrator and block timesteps.
+    // On entry ax/ay/az hold the total accelerations. Leaving it at zero only costs those
+    // schemes some accuracy
+    fn jerk_on(&self, _i: usize, _particles: &ParticleStore) -> (Real, Real, Real) {
+        (0.0, 0.0, 0.0)
+    }
+
+    // Potential energy per unit mass of object i, for fields that have one, so the
+    // conservation diagnostics can count it. Fields that don't (drag) show up there as drift
+    fn potential_of(&self, _i: usize, _particles: &ParticleStore) -> f64 {
+        0.0
+    }
+}
+
+// The same pull everywhere, like gravity near a planet's surface
+pub struct UniformField {
+    pub acceleration: (Real, Real, Real),
+}
+
+impl ForceField for UniformField {
+    fn name(&self) -> &str {
+        "Uniform Field"
+    }
+
+    fn acceleration_on(&self, _i: usize, _particles: &ParticleStore) -> (Real, Real, Real) {
+        self.acceleration
+    }
+
+    // -g·r, zero at the origin
+    fn potential_of(&self, i: usize, particles: &ParticleStore) -> f64 {
+        let (gx, gy, gz) = self.acceleration;
+        -(to_f64(gx) * to_f64(particles.x[i]) + to_f64(gy) * to_f64(particles.y[i]) + to_f64(gz) * to_f64(particles.z[i]))
+    }
+}
+
+// Friction against a medium at rest: a = -(linear + quadratic |v|) v. The linear part is
+// Stokes drag in a viscous gas, the quadratic part ram pressure at high speed
+pub struct Drag {
+    pub linear: Real,    // per unit time
+    pub quadratic: Real, // per unit length
+}
+
+impl ForceField for Drag {
+    fn name(&self) -> &str {
+        "Drag"
+    }
+
+    fn acceleration_on(&self, i: usize, particles: &ParticleStore) -> (Real, Real, Real) {
+        let obj = particles.particle(i);
+        let speed = (obj.vx * obj.vx + obj.vy * obj.vy + obj.vz * obj.vz).sqrt();
+        let rate = self.linear + self.quadratic * speed;
+        (-rate * obj.vx, -rate * obj.vy, -rate * obj.vz)
+    }
+
+    // d/dt of -(k1 + k2 |v|) v = -(k1 + k2 |v|) a - k2 (v·a / |v|) v
+    fn jerk_on(&self, i: usize, particles: &ParticleStore) -> (Real, Real, Real) {
+        let obj = particles.particle(i);
+        let speed = (obj.vx * obj.vx + obj.vy * obj.vy + obj.vz * obj.vz).sqrt();
+        let rate = self.linear + self.quadratic * speed;
+        let speeding_up = if speed > 0.0 {
+            self.quadratic * (obj.vx * obj.ax + obj.vy * obj.ay + obj.vz * obj.az) / speed
+        } else {
+            0.0
+        };
+        (-rate * obj.ax - speeding_up * obj.vx,
+         -rate * obj.ay - speeding_up * obj.vy,
+         -rate * obj.az - speeding_up * obj.vz)
+    }
+}
+
+// Any analytic potential with a known pull: the dark matter halos are one. The galaxy's own
+// halo lives in the gravity engine; this is for adding more
+impl ForceField for DarkMatterHalo {
+    fn name(&self) -> &str {
+        self.profile.name()
+    }
+
+    fn acceleration_on(&self, i: usize, particles: &ParticleStore) -> (Real, Real, Real) {
+        self.acceleration_at(particles.x[i], particles.y[i], particles.z[i])
+    }
+
+    fn jerk_on(&self, i: usize, particles: &ParticleStore) -> (Real, Real, Real) {
+        let obj = particles.particle(i);
+        self.jerk_at((obj.x, obj.y, obj.z), (obj.vx, obj.vy, obj.vz))
+    }
+
+    fn potential_of(&self, i: usize, particles: &ParticleStore) -> f64 {
+        self.potential_at(to_f64(particles.x[i]), to_f64(particles.y[i]), to_f64(particles.z[i]))
+    }
+}
+
+// A field from a closure, for one-off physics without a type of its own:
+//   engine.add_force_field(ClosureField::new("Wind", |_, _| (0.1, 0.0, 0.0)));
+pub struct ClosureField<F> {
+    name: String,
+    acceleration: F,
+}
+
+impl<F> ClosureField<F>
+where
+    F: Fn(&Particle, &CelestialObject) -> (Real, Real, Real) + Send + Sync,
+{
+    pub fn new(name: &str, acceleration: F) -> Self {
+        ClosureField {
+            name: name.to_string(),
+            acceleration,
+        }
+    }
+}
+
+impl<F> ForceField for ClosureField<F>
+where
+    F: Fn(&Particle, &CelestialObject) -> (Real, Real, Real) + Send + Sync,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn acceleration_on(&self, i: usize, particles: &ParticleStore) -> (Real, Real, Real) {
+        (self.acceleration)(&particles.particle(i), &particles.objects[i])
+    }
+}
@@ -0,0 +1,221 @@
+use crate::gravity::G;
+use crate::real::{to_f64, Real};
+
+// Density profile of a dark matter halo. In units of x = r / a (a the scale radius):
+//   NFW:        ρ ∝ 1 / (x (1 + x)²)   - cuspy centre, falls off as r⁻³ (Navarro, Frenk & White 1996)
+//   Isothermal: ρ ∝ 1 / (1 + x²)       - cored, r⁻² outside, so the rotation curve goes flat
+//   Hernquist:  ρ ∝ 1 / (x (1 + x)³)   - cuspy centre, r⁻⁴ outside and a finite total mass (Hernquist 1990)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HaloProfile {
+    None, // no halo: only the objects themselves pull
+    Nfw,
+    Isothermal,
+    Hernquist,
+}
+
+impl HaloProfile {
+    pub fn name(&self) -> &'static str {
+        match self {
+            HaloProfile::None => "None",
+            HaloProfile::Nfw => "NFW",
+            HaloProfile::Isothermal => "Isothermal",
+            HaloProfile::Hernquist => "Hernquist",
+        }
+    }
+
+    // Cycle through the profiles (bound to the H key)
+    pub fn next(&self) -> Self {
+        match self {
+            HaloProfile::None => HaloProfile::Nfw,
+            HaloProfile::Nfw => HaloProfile::Isothermal,
+            HaloProfile::Isothermal => HaloProfile::Hernquist,
+            HaloProfile::Hernquist => HaloProfile::None,
+        }
+    }
+
+    // Mass inside x, in units of the profile's mass scale M_s:
+    //   NFW ln(1 + x) - x / (1 + x),  isothermal x - atan x,  Hernquist x² / (1 + x)²
+    // Near the centre the first two are differences of nearly equal numbers, which in f32
+    // can come out as nothing or even negative, so there they're Taylor series instead
+    fn enclosed(&self, x: Real) -> Real {
+        let near_centre = x < 0.05;
+        match self {
+            HaloProfile::None => 0.0,
+            HaloProfile::Nfw if near_centre => x * x * (0.5 + x * (-2.0 / 3.0 + x * (0.75 - x * 0.8))),
+            HaloProfile::Nfw => x.ln_1p() - x / (1.0 + x),
+            HaloProfile::Isothermal if near_centre => x * x * x * (1.0 / 3.0 - x * x / 5.0),
+            HaloProfile::Isothermal => x - x.atan(),
+            HaloProfile::Hernquist => x * x / ((1.0 + x) * (1.0 + x)),
+        }
+    }
+
+    // d(enclosed)/dx, which is 4π r² ρ in the same units
+    fn enclosed_slope(&self, x: Real) -> Real {
+        match self {
+            HaloProfile::None => 0.0,
+            HaloProfile::Nfw => x / ((1.0 + x) * (1.0 + x)),
+            HaloProfile::Isothermal => x * x / (1.0 + x * x),
+            HaloProfile::Hernquist => 2.0 * x / ((1.0 + x) * (1.0 + x) * (1.0 + x)),
+        }
+    }
+
+    // Potential in units of G M_s / a. The isothermal one grows without bound
+    // (logarithmically), so it's zero at the centre instead of at infinity
+    fn potential(&self, x: f64) -> f64 {
+        // atan(x) / x and ln(1 + x) / x both go to 1 at the centre
+        let near_centre = x < 1e-6;
+        match self {
+            HaloProfile::None => 0.0,
+            HaloProfile::Nfw => if near_centre { -1.0 } else { -x.ln_1p() / x },
+            HaloProfile::Isothermal => {
+                let core = if near_centre { 1.0 } else { x.atan() / x };
+                core + 0.5 * (x * x).ln_1p() - 1.0
+            }
+            HaloProfile::Hernquist => -1.0 / (1.0 + x),
+        }
+    }
+}
+
+// A spherical dark matter halo around a galaxy, felt by every object as a fixed external
+// potential rather than through particles. It doesn't move and nothing pulls back on it,
+// so energy (with the halo's potential included) and angular momentum about its centre
+// are kept, but momentum isn't
+#[derive(Debug, Clone, Copy)]
+pub struct DarkMatterHalo {
+    pub profile: HaloProfile,
+    pub centre: (Real, Real, Real),
+    pub scale_radius: Real,
+    // M_s, picked so that every profile puts the same mass inside the scale radius.
+    // Switching profile then leaves the inner rotation curve roughly alone and changes
+    // how it carries on further out
+    mass_scale: Real,
+}
+
+impl DarkMatterHalo {
+    // A halo with `mass` inside `scale_radius`
+    pub fn new(profile: HaloProfile, centre: (Real, Real, Real), mass: Real, scale_radius: Real) -> Self {
+        DarkMatterHalo {
+            profile,
+            centre,
+            scale_radius,
+            mass_scale: mass / profile.enclosed(1.0),
+        }
+    }
+
+    // Halo mass inside radius r of the centre
+    pub fn enclosed_mass(&self, r: Real) -> Real {
+        self.mass_scale * self.profile.enclosed(r / self.scale_radius)
+    }
+
+    // Speed of a circular orbit at radius r from the halo alone. Add it in quadrature to
+    // the speed from the mass at the centre: v² = v_centre² + v_halo²
+    pub fn circular_speed(&self, r: Real) -> Real {
+        if r <= 0.0 {
+            return 0.0;
+        }
+        (G * self.enclosed_mass(r) / r).sqrt()
+    }
+
+    // Pull towards the centre, G M(r) / r² along -r̂. Zero at the centre itself
+    pub fn acceleration_at(&self, x: Real, y: Real, z: Real) -> (Real, Real, Real) {
+        let (dx, dy, dz) = (x - self.centre.0, y - self.centre.1, z - self.centre.2);
+        let r = (dx * dx + dy * dy + dz * dz).sqrt();
+        if r <= 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        let factor = -G * self.enclosed_mass(r) / (r * r * r);
+        (factor * dx, factor * dy, factor * dz)
+    }
+
+    // Rate of change of that pull for something moving with (vx, vy, vz), for the Hermite
+    // integrator and block timesteps. With g(r) = G M(r) / r² and n = r̂:
+    //   da/dt = -g' (n·v) n - (g / r) (v - (n·v) n),   g' = G 4πρ - 2 g / r
+    pub fn jerk_at(&self, position: (Real, Real, Real), velocity: (Real, Real, Real)) -> (Real, Real, Real) {
+        let (dx, dy, dz) = (position.0 - self.centre.0, position.1 - self.centre.1, position.2 - self.centre.2);
+        let r = (dx * dx + dy * dy + dz * dz).sqrt();
+        if r <= 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+        let (nx, ny, nz) = (dx / r, dy / r, dz / r);
+        let (vx, vy, vz) = velocity;
+        let radial = nx * vx + ny * vy + nz * vz;
+
+        let x = r / self.scale_radius;
+        let g = G * self.enclosed_mass(r) / (r * r);
+        let slope = G * self.mass_scale * self.profile.enclosed_slope(x) / (self.scale_radius * r * r) - 2.0 * g / r;
+        let across = g / r;
+        (-slope * radial * nx - across * (vx - radial * nx),
+         -slope * radial * ny - across * (vy - radial * ny),
+         -slope * radial * nz - across * (vz - radial * nz))
+    }
+
+    // Potential energy per unit mass at (x, y, z), for the conservation diagnostics
+    pub fn potential_at(&self, x: f64, y: f64, z: f64) -> f64 {
+        let (dx, dy, dz) = (x - to_f64(self.centre.0), y - to_f64(self.centre.1), z - to_f64(self.centre.2));
+        let r = (dx * dx + dy * dy + dz * dz).sqrt();
+        let scale = to_f64(self.scale_radius);
+        to_f64(G) * to_f64(self.mass_scale) / scale * self.profile.potential(r / scale)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROFILES: [HaloProfile; 3] = [HaloProfile::Nfw, HaloProfile::Isothermal, HaloProfile::Hernquist];
+
+    fn halo(profile: HaloProfile) -> DarkMatterHalo {
+        DarkMatterHalo::new(profile, (10.0, -5.0, 3.0), 1e6, 250.0)
+    }
+
+    // Points at these distances from the centre, off every axis. The first is inside the
+    // range where the enclosed mass switches to its Taylor series
+    fn points(halo: &DarkMatterHalo) -> Vec<(f64, (f64, f64, f64))> {
+        let direction = (0.48, -0.6, 0.64);
+        let (cx, cy, cz) = (to_f64(halo.centre.0), to_f64(halo.centre.1), to_f64(halo.centre.2));
+        [5.0, 60.0, 250.0, 900.0, 4000.0].into_iter()
+            .map(|r| (r, (cx + r * direction.0, cy + r * direction.1, cz + r * direction.2)))
+            .collect()
+    }
+
+    // The pull is minus the gradient of the potential, by central differences
+    #[test]
+    fn acceleration_is_minus_the_gradient_of_the_potential() {
+        for profile in PROFILES {
+            let halo = halo(profile);
+            for (r, (x, y, z)) in points(&halo) {
+                let h = 1e-3 * r;
+                let slope = |dx: f64, dy: f64, dz: f64| {
+                    (halo.potential_at(x + dx, y + dy, z + dz) - halo.potential_at(x - dx, y - dy, z - dz)) / (2.0 * h)
+                };
+                let numeric = [-slope(h, 0.0, 0.0), -slope(0.0, h, 0.0), -slope(0.0, 0.0, h)];
+                let (ax, ay, az) = halo.acceleration_at(x as Real, y as Real, z as Real);
+                let size = numeric.iter().map(|a| a * a).sum::<f64>().sqrt();
+                for (axis, (a, n)) in [to_f64(ax), to_f64(ay), to_f64(az)].into_iter().zip(numeric).enumerate() {
+                    assert!((a - n).abs() < 1e-3 * size, "{} at r = {r}, axis {axis}: {a:e} vs {n:e}", profile.name());
+                }
+            }
+        }
+    }
+
+    // v² = G M(r) / r with the textbook enclosed masses
+    #[test]
+    fn circular_speed_matches_the_analytic_formula() {
+        let enclosed = |profile: HaloProfile, x: f64| match profile {
+            HaloProfile::Nfw => x.ln_1p() - x / (1.0 + x),
+            HaloProfile::Isothermal => x - x.atan(),
+            HaloProfile::Hernquist => x * x / ((1.0 + x) * (1.0 + x)),
+            HaloProfile::None => 0.0,
+        };
+        for profile in PROFILES {
+            let halo = halo(profile);
+            for r in [5.0, 60.0, 250.0, 900.0, 4000.0] {
+                let mass = 1e6 * enclosed(profile, r / 250.0) / enclosed(profile, 1.0);
+                let expected = (to_f64(G) * mass / r).sqrt();
+                let speed = to_f64(halo.circular_speed(r as Real));
+                assert!((speed - expected).abs() < 1e-4 * expected, "{} at r = {r}: {speed} vs {expected}", profile.name());
+            }
+            assert!((to_f64(halo.enclosed_mass(250.0)) - 1e6).abs() < 1.0);
+        }
+    }
+}
@@ -0,0 +1,28 @@
+// The simulation itself, without the window: objects, gravity, integrators and the other
+// physics engines. The binary in main.rs draws it with macroquad. Other programs can use
+// it headless, e.g. to step a `PhysicsEngine` with their own `ForceField`s registered
+pub mod universe;
+pub mod physics;
+pub mod force_field;
+pub mod objects;
+pub mod particles;
+pub mod gravity;
+pub mod halo;
+pub mod expansion;
+pub mod collisions;
+pub mod accretion;
+pub mod tidal;
+pub mod radiation;
+pub mod gravitational_waves;
+pub mod events;
+pub mod diagnostics;
+pub mod units;
+pub mod real;
+mod fmm;
+mod particle_mesh;
+mod quadtree;
+#[cfg(test)]
+mod test_support;
+
+pub use force_field::ForceField;
+pub use physics::PhysicsEngine;
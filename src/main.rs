@@ -1,14 +1,14 @@
 use macroquad::prelude::*;
 
-mod universe;
-mod physics;
-mod objects;
-mod gravity;
-mod expansion;
 mod rendering;
+mod camera;
 
-use universe::Universe;
-use rendering::Renderer;
+use bigbang_simulator::universe::{BoundaryCondition, Universe};
+use bigbang_simulator::gravity::GravitySolver;
+use bigbang_simulator::objects::ObjectType;
+use bigbang_simulator::units;
+use rendering::{HudState, Renderer};
+use camera::Camera;
 
 #[macroquad::main("Big Bang Simulator")]
 async fn main() {
@@ -16,26 +16,27 @@ async fn main() {
     let mut current_height = screen_height();
     
     let mut universe = Universe::new(current_width, current_height);
-    let mut renderer = Renderer::new();
+    let renderer = Renderer::new();
     
     let mut expansion_speed = 0.1;
-    let mut zoom = 1.0;
-    let mut camera_offset_x = 0.0;
-    let mut camera_offset_y = 0.0;
+    let mut camera = Camera::new(current_width, current_height);
     let mut is_dragging = false;
     let mut drag_start_pos = (0.0, 0.0);
     
     let mut show_info = false;
     let mut selected_object_info: Option<String> = None;
     let mut selected_object_index: Option<usize> = None;
+    let mut selected_removals = 0; // the store's removal count when the selection was made
     let mut show_detail_modal = false;
     let mut fullscreen = false;
+    let mut show_diagnostics = false;
     
     // Main loop
     loop {
         // Update window dimensions if resized
         current_width = screen_width();
         current_height = screen_height();
+        camera.resize(current_width, current_height);
         
         // Cosmic speed 
         if is_key_pressed(KeyCode::Key1) {
@@ -56,28 +57,129 @@ async fn main() {
             continue;
         }
         
+        // Switch gravity solver
+        if is_key_pressed(KeyCode::G) {
+            let gravity = &mut universe.physics_engine.gravity_engine;
+            gravity.solver = gravity.solver.next();
+            
+            // The mesh solver assumes a periodic box; every other solver keeps the user's boundary
+            if gravity.solver == GravitySolver::ParticleMesh {
+                universe.boundary = BoundaryCondition::Periodic;
+            }
+            universe.physics_engine.invalidate_forces();
+        }
+        
+        // Trade the gravity solver's accuracy against speed
+        if is_key_pressed(KeyCode::LeftBracket) || is_key_pressed(KeyCode::RightBracket) {
+            universe.physics_engine.gravity_engine.adjust_accuracy(is_key_pressed(KeyCode::RightBracket), universe.three_d);
+            universe.physics_engine.invalidate_forces();
+        }
+        
+        // Switch how gravity is softened at close range
+        if is_key_pressed(KeyCode::K) {
+            let gravity = &mut universe.physics_engine.gravity_engine;
+            gravity.softening_kernel = gravity.softening_kernel.next();
+            universe.physics_engine.invalidate_forces();
+            // The potential energy changes with the kernel, so drift is measured afresh
+            universe.restart_diagnostics();
+        }
+        
+        // Toggle periodic boundaries
+        if is_key_pressed(KeyCode::B) {
+            universe.boundary = universe.boundary.toggled();
+        }
+        
+        // Switch integration scheme
+        if is_key_pressed(KeyCode::I) {
+            universe.physics_engine.cycle_integrator();
+        }
+        
+        // Toggle relativistic corrections near compact objects
+        if is_key_pressed(KeyCode::P) {
+            let gravity = &mut universe.physics_engine.gravity_engine;
+            gravity.post_newtonian = !gravity.post_newtonian;
+            universe.physics_engine.invalidate_forces();
+        }
+        
+        // Toggle gravitational-wave radiation reaction for compact binaries
+        if is_key_pressed(KeyCode::W) {
+            let gravity = &mut universe.physics_engine.gravity_engine;
+            gravity.radiation_reaction = !gravity.radiation_reaction;
+            universe.physics_engine.invalidate_forces();
+        }
+        
+        // Switch what happens to objects that get too fast
+        if is_key_pressed(KeyCode::L) {
+            universe.physics_engine.velocity_policy = universe.physics_engine.velocity_policy.next();
+        }
+        
+        // Switch what starlight does to small bodies
+        if is_key_pressed(KeyCode::O) {
+            universe.set_radiation_mode(universe.radiation_engine.mode.next());
+        }
+        
+        // Toggle comets shedding dust near stars (needs radiation on)
+        if is_key_pressed(KeyCode::J) {
+            universe.radiation_engine.comet_dust = !universe.radiation_engine.comet_dust;
+        }
+        
+        // Switch collision handling
+        if is_key_pressed(KeyCode::C) {
+            universe.collision_engine.mode = universe.collision_engine.mode.next();
+        }
+        
+        // Toggle violent impacts shattering bodies into debris
+        if is_key_pressed(KeyCode::X) {
+            universe.collision_engine.fragmentation = !universe.collision_engine.fragmentation;
+        }
+        
+        // Toggle black hole accretion
+        if is_key_pressed(KeyCode::A) {
+            universe.accretion_engine.enabled = !universe.accretion_engine.enabled;
+        }
+        
+        // Toggle tidal disruption
+        if is_key_pressed(KeyCode::T) {
+            universe.tidal_engine.enabled = !universe.tidal_engine.enabled;
+        }
+        
+        // Show the conservation diagnostics
+        if is_key_pressed(KeyCode::D) {
+            show_diagnostics = !show_diagnostics;
+        }
+        
+        // Moon-forming giant impact demo, binary black hole inspiral demo, switching the
+        // dark matter halo, and switching between the flat and the 3D universe
+        if is_key_pressed(KeyCode::M) || is_key_pressed(KeyCode::N) || is_key_pressed(KeyCode::H)
+            || is_key_pressed(KeyCode::V) {
+            if is_key_pressed(KeyCode::M) {
+                universe.create_giant_impact();
+            } else if is_key_pressed(KeyCode::N) {
+                universe.create_binary_black_hole();
+            } else if is_key_pressed(KeyCode::H) {
+                universe.set_halo_profile(universe.halo_profile.next());
+            } else {
+                universe.set_three_d(!universe.three_d);
+                camera.set_perspective(universe.three_d);
+            }
+            selected_object_info = None;
+            selected_object_index = None;
+            show_info = false;
+            show_detail_modal = false;
+        }
+        
         if is_key_pressed(KeyCode::Tab) && show_info && selected_object_index.is_some() {
             show_detail_modal = true;
         }
         
-        // Zoom in/out with mouse wheel
+        // Zoom in/out with mouse wheel, keeping the spot under the mouse in place
         let wheel = mouse_wheel().1;
         if wheel != 0.0 {
             let mouse_pos = mouse_position();
-            // Convert mouse position to world space for smooth zoom
-            let mouse_world_x = (mouse_pos.0 - current_width / 2.0 - camera_offset_x) / zoom;
-            let mouse_world_y = (mouse_pos.1 - current_height / 2.0 - camera_offset_y) / zoom;
-            
-            let old_zoom = zoom;
-            zoom *= 1.0 + wheel * 0.1;
-            zoom = zoom.clamp(0.05, 30.0); // Dont zoom too far!
-            
-            // Keep mouse position stable during zoom
-            camera_offset_x += mouse_world_x * (old_zoom - zoom);
-            camera_offset_y += mouse_world_y * (old_zoom - zoom);
+            camera.zoom_at(mouse_pos.0, mouse_pos.1, 1.0 + wheel * 0.1);
         }
         
-        // Right click drag to pan around
+        // Right click drag to pan around (to orbit the camera, in 3D)
         if is_mouse_button_down(MouseButton::Right) {
             let mouse_pos = mouse_position();
             
@@ -87,19 +189,27 @@ async fn main() {
             } else {
                 let dx = mouse_pos.0 - drag_start_pos.0;
                 let dy = mouse_pos.1 - drag_start_pos.1;
-                camera_offset_x += dx * 0.5;
-                camera_offset_y += dy * 0.5;
+                if camera.perspective {
+                    camera.orbit(dx, dy);
+                } else {
+                    camera.offset_x += dx * 0.5;
+                    camera.offset_y += dy * 0.5;
+                }
                 drag_start_pos = mouse_pos;
             }
         } else {
             is_dragging = false;
         }
         
+        // Arrow keys orbit the 3D camera too
+        let turn = |minus: KeyCode, plus: KeyCode| {
+            (is_key_down(plus) as i32 - is_key_down(minus) as i32) as f32 * 2.0
+        };
+        camera.orbit(turn(KeyCode::Left, KeyCode::Right), turn(KeyCode::Up, KeyCode::Down));
+        
         // Reset view with middle click or R key
         if is_mouse_button_pressed(MouseButton::Middle) || is_key_pressed(KeyCode::R) {
-            camera_offset_x = 0.0;
-            camera_offset_y = 0.0;
-            zoom = 1.0;
+            camera.reset();
         }
         
         // Time manipulation controls
@@ -114,58 +224,58 @@ async fn main() {
         if is_mouse_button_pressed(MouseButton::Left) && !is_dragging && !show_detail_modal {
             let mouse_pos = mouse_position();
             
-            // Convert click to world coordinates
-            let world_x = (mouse_pos.0 - current_width / 2.0 - camera_offset_x) / zoom;
-            let world_y = (mouse_pos.1 - current_height / 2.0 - camera_offset_y) / zoom;
-            
             // Check if we clicked on something interesting
-            if let Some((obj_idx, obj)) = universe.get_object_at_position(world_x, world_y, zoom) {
+            if let Some(obj_idx) = camera.object_at(&universe.particles, mouse_pos.0, mouse_pos.1) {
+                let (body, obj) = (universe.particles.particle(obj_idx), &universe.particles.objects[obj_idx]);
                 selected_object_index = Some(obj_idx);
+                selected_removals = universe.particles.removals();
                 
                 // Translate enum to human-readable string
-                let object_type_str = match obj.object_type {
-                    crate::objects::ObjectType::Star => "Star",
-                    crate::objects::ObjectType::Planet => "Planet",
-                    crate::objects::ObjectType::GalaxyCenter => "Galaxy Center",
-                    crate::objects::ObjectType::DarkMatter => "Dark Matter",
-                    crate::objects::ObjectType::DarkEnergy => "Dark Energy",
-                    crate::objects::ObjectType::BlackHole => "Black Hole",
-                    crate::objects::ObjectType::NeutronStar => "Neutron Star",
-                    crate::objects::ObjectType::Pulsar => "Pulsar",
-                    crate::objects::ObjectType::WhiteDwarf => "White Dwarf",
-                    crate::objects::ObjectType::Comet => "Comet",
-                    crate::objects::ObjectType::Asteroid => "Asteroid",
-                };
+                let object_type_str = obj.object_type.name();
                 
                 // Extra info for special objects
                 let extra_info = match obj.object_type {
-                    crate::objects::ObjectType::NeutronStar => {
+                    ObjectType::Star => {
+                        format!("\nLuminosity: {:.2} Lsun", units::luminosity_to_solar(obj.luminosity))
+                    }
+                    ObjectType::NeutronStar => {
                         format!("\nRotation: {:.3}s\nMag Field: {:.0e} T", 
                             obj.rotation_period, obj.magnetic_field)
                     }
-                    crate::objects::ObjectType::Pulsar => {
+                    ObjectType::Pulsar => {
                         format!("\nRotation: {:.3}s\nMag Field: {:.0e} T\nBeam Period: {:.2}s", 
                             obj.rotation_period, obj.magnetic_field, 1.0/obj.rotation_period)
                     }
-                    crate::objects::ObjectType::WhiteDwarf => {
+                    ObjectType::WhiteDwarf => {
                         "\nType: Degenerate Star\nDensity: ~1 ton/cm³".to_string()
                     }
-                    crate::objects::ObjectType::BlackHole => {
-                        format!("\nEvent Horizon: {:.2} km", obj.event_horizon_radius / 1000.0)
+                    ObjectType::Dust => {
+                        let c = universe.physics_engine.gravity_engine.speed_of_light;
+                        match universe.radiation_engine.beta_of(&universe.particles, obj_idx, c) {
+                            Some(beta) => format!("\nRadiation/Gravity (beta): {:.2}", beta),
+                            None => "".to_string(),
+                        }
+                    }
+                    ObjectType::BlackHole => {
+                        format!("\nEvent Horizon: {}", units::format_length(obj.event_horizon_radius))
                     }
                     _ => "".to_string(),
                 };
                 
                 // Build info string
                 selected_object_info = Some(format!(
-                    "Type: {}\nMass: {:.2e} kg\nRadius: {:.1} km\nSpeed: {:.2} km/s\nTemperature: {:.0} K\nAge: {:.1} Myr\nPosition: ({:.0}, {:.0}) km{}",
+                    "Type: {}\nMass: {}\nRadius: {}\nSpeed: {}\nTemperature: {:.0} K\nAge: {}\nPosition: {} AU{}",
                     object_type_str,
-                    obj.mass,
-                    obj.radius / 1000.0,
-                    (obj.vx * obj.vx + obj.vy * obj.vy).sqrt() / 1000.0,
+                    units::format_mass(body.mass),
+                    units::format_length(obj.radius),
+                    units::format_speed((body.vx * body.vx + body.vy * body.vy + body.vz * body.vz).sqrt()),
                     obj.temperature,
-                    obj.get_age(universe.age) / (1e6 * 365.0 * 24.0 * 3600.0),
-                    obj.x / 1000.0, obj.y / 1000.0,
+                    units::format_time(obj.get_age(universe.age)),
+                    if universe.three_d {
+                        format!("({:.0}, {:.0}, {:.0})", body.x, body.y, body.z)
+                    } else {
+                        format!("({:.0}, {:.0})", body.x, body.y)
+                    },
                     extra_info
                 ));
                 show_info = true;
@@ -201,24 +311,41 @@ async fn main() {
         // Update the universe 
         universe.update(expansion_speed * time_scale);
         
+        // Mergers, accretion, tidal disruption and the boundary remove bodies, which shifts
+        // the indices after them, so the selected index may now be someone else
+        if selected_object_index.is_some() && universe.particles.removals() != selected_removals {
+            selected_object_info = None;
+            selected_object_index = None;
+            show_info = false;
+            show_detail_modal = false;
+        }
+        
         clear_background(Color::new(0.02, 0.02, 0.05, 1.0));
         
-        renderer.draw_universe(&universe, zoom, camera_offset_x, camera_offset_y, current_width, current_height);
+        renderer.draw_universe(&universe, &camera, current_width, current_height);
+        
+        renderer.draw_events(&universe, current_height);
+        
+        if show_diagnostics && !show_detail_modal {
+            renderer.draw_diagnostics(&universe, current_width, current_height);
+        }
         
         // Show object info panel
-        if show_info && !show_detail_modal {
-            if let Some(ref info) = selected_object_info {
-                renderer.draw_object_info_with_button(info, current_width);
-            }
+        if show_info && !show_detail_modal && let Some(ref info) = selected_object_info {
+            renderer.draw_object_info_with_button(info, current_width);
+        }
+        
+        // Gravitational-wave readout when a compact binary is selected
+        if show_info && !show_detail_modal && let Some(obj_idx) = selected_object_index {
+            renderer.draw_binary_readout(&universe, obj_idx, current_width);
         }
         
         // Show detailed modal view
-        if show_detail_modal && selected_object_index.is_some() {
-            if let Some(obj_idx) = selected_object_index {
-                if let Some(obj) = universe.get_object_by_index(obj_idx) {
-                    renderer.draw_detail_modal(obj, &mut show_detail_modal, current_width, current_height);
-                }
-            }
+        if show_detail_modal
+            && let Some(obj_idx) = selected_object_index
+            && let Some((body, obj)) = universe.get_object_by_index(obj_idx)
+        {
+            renderer.draw_detail_modal(&body, obj, &mut show_detail_modal, current_width, current_height);
         }
         
         //  scale indicator
@@ -230,8 +357,14 @@ async fn main() {
         
         // When modal is open
         if !show_detail_modal {
-            renderer.draw_help(expansion_speed, universe.age, zoom, camera_offset_x, camera_offset_y, 
-                             time_scale_text, fullscreen, current_width, current_height);
+            renderer.draw_help(&universe, &HudState {
+                expansion_speed,
+                zoom: camera.zoom,
+                time_scale: time_scale_text,
+                fullscreen,
+                screen_width: current_width,
+                screen_height: current_height,
+            });
         }
         
         // Wait for next frame
@@ -1,11 +1,12 @@
 use rand::Rng;
+use crate::units;
+use crate::real::Real;
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ObjectType {
     Star,
     Planet,
     GalaxyCenter,
-    DarkMatter,
     DarkEnergy,
     BlackHole,
     NeutronStar,
@@ -13,33 +14,53 @@ pub enum ObjectType {
     WhiteDwarf,
     Comet,
     Asteroid,
+    Dust, // grains small enough that starlight pushes them around
     // Galaxy
 }
 
+impl ObjectType {
+    // Black holes and neutron stars: dense enough that relativity matters near them
+    pub fn is_compact(&self) -> bool {
+        matches!(self, ObjectType::BlackHole | ObjectType::NeutronStar | ObjectType::Pulsar)
+    }
+    
+    // Human-readable name for the UI
+    pub fn name(&self) -> &'static str {
+        match self {
+            ObjectType::Star => "Star",
+            ObjectType::Planet => "Planet",
+            ObjectType::GalaxyCenter => "Galaxy Center",
+            ObjectType::DarkEnergy => "Dark Energy",
+            ObjectType::BlackHole => "Black Hole",
+            ObjectType::NeutronStar => "Neutron Star",
+            ObjectType::Pulsar => "Pulsar",
+            ObjectType::WhiteDwarf => "White Dwarf",
+            ObjectType::Comet => "Comet",
+            ObjectType::Asteroid => "Asteroid",
+            ObjectType::Dust => "Dust",
+        }
+    }
+}
+
+// What a body is and how it looks. Where it is, how it moves and how heavy it is live in
+// the ParticleStore, at the same index
 #[derive(Debug, Clone)]
 pub struct CelestialObject {
-    pub x: f32,
-    pub y: f32,
-    pub vx: f32,
-    pub vy: f32,
-    pub ax: f32,
-    pub ay: f32,
-    pub radius: f32,
-    pub mass: f32,
+    pub radius: Real,
     pub object_type: ObjectType,
     pub color: (u8, u8, u8),
     pub creation_time: f64,
-    pub luminosity: f32,
-    pub temperature: f32,
+    pub luminosity: Real,
+    pub temperature: Real,
     pub is_black_hole: bool,
-    pub event_horizon_radius: f32,
-    pub rotation_period: f32,
-    pub magnetic_field: f32,
+    pub event_horizon_radius: Real,
+    pub rotation_period: Real,
+    pub magnetic_field: Real,
 }
 
 impl CelestialObject {
-    pub fn new(x: f32, y: f32, vx: f32, vy: f32, radius: f32, mass: f32, 
-               object_type: ObjectType, creation_time: f64) -> Self {
+    // `mass` sets the brightness and the event horizon; the store keeps the mass itself
+    pub fn new(radius: Real, mass: Real, object_type: ObjectType, creation_time: f64) -> Self {
         let mut rng = rand::thread_rng();
         
         let (color, luminosity, temperature, is_black_hole, rotation_period, magnetic_field) = 
@@ -64,9 +85,6 @@ impl CelestialObject {
             ObjectType::GalaxyCenter => {
                 ((240, 240, 160), mass / 5000.0, 6000.0, false, 0.0, 0.0)
             }
-            ObjectType::DarkMatter => {
-                ((60, 60, 140), 0.0, 0.0, false, 0.0, 0.0)  // dark purple
-            }
             ObjectType::DarkEnergy => {
                 ((120, 40, 180), 0.0, 0.0, false, 0.0, 0.0)  // purple
             }
@@ -92,40 +110,35 @@ impl CelestialObject {
             ObjectType::Asteroid => {
                 ((120, 120, 120), 0.0, 0.0, false, 0.0, 0.0)  // gray rock
             }
+            ObjectType::Dust => {
+                ((210, 190, 160), 0.0, 0.0, false, 0.0, 0.0)  // sunlit dust
+            }
         };
         
-        // calculate event horizon for black holes  - if it is one -
-        let event_horizon_radius = if is_black_hole {
-            2.0 * 6.67430e-11_f32 * mass / (299792458.0 * 299792458.0) 
-        } else {
-            0.0
-        };
-        
-        CelestialObject {
-            x,
-            y,
-            vx,
-            vy,
-            ax: 0.0,
-            ay: 0.0,
+        let mut object = CelestialObject {
             radius,
-            mass,
             object_type,
             color,
             creation_time,
             luminosity,
             temperature,
             is_black_hole,
-            event_horizon_radius,
+            event_horizon_radius: 0.0,
             rotation_period,
             magnetic_field,
-        }
+        };
+        object.update_event_horizon(mass);
+        object
     }
     
-    pub fn update(&mut self, time_scale: f32) {
-        // Update position 
-        self.x += self.vx * time_scale * 0.1;
-        self.y += self.vy * time_scale * 0.1;
+    // calculate event horizon for black holes  - if it is one - (again after the mass changes).
+    // In simulation units, with the real speed of light
+    pub fn update_event_horizon(&mut self, mass: Real) {
+        self.event_horizon_radius = if self.is_black_hole {
+            units::schwarzschild_radius(mass)
+        } else {
+            0.0
+        };
     }
     
     pub fn get_age(&self, universe_age: f64) -> f64 {
@@ -134,7 +147,7 @@ impl CelestialObject {
 }
 
 // Make stars colorful based on temperature
-fn temperature_to_color(temperature: f32) -> (u8, u8, u8) {
+fn temperature_to_color(temperature: Real) -> (u8, u8, u8) {
     let normalized_temp = temperature / 10000.0;
     
     if normalized_temp < 0.4 {
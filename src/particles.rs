@@ -0,0 +1,297 @@
+use crate::gravity::{SofteningKernel, G};
+use crate::objects::CelestialObject;
+use crate::real::{to_f64, Real};
+
+// Width of the force kernel. Eight f32 lanes fill an AVX register (two, in f64 mode); the inner loops below
+// work on fixed-size arrays of this width so the compiler turns them into SIMD code
+pub const LANES: usize = 8;
+
+// One body's physics state as a plain value, for adding bodies to the store and for the
+// code that works on a pair at a time (mergers, bounces, debris). z points out of the
+// screen. Flat scenes leave it (and vz) at 0, where nothing ever pulls a body off the plane
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Particle {
+    pub x: Real,
+    pub y: Real,
+    pub z: Real,
+    pub vx: Real,
+    pub vy: Real,
+    pub vz: Real,
+    pub ax: Real,
+    pub ay: Real,
+    pub az: Real,
+    pub mass: Real,
+}
+
+impl Particle {
+    // A body in the z = 0 plane, moving in it
+    pub fn new(x: Real, y: Real, vx: Real, vy: Real, mass: Real) -> Self {
+        Particle { x, y, vx, vy, mass, ..Particle::default() }
+    }
+}
+
+// One body on its way into or out of the store: its physics state and what it is
+pub type Body = (Particle, CelestialObject);
+
+// Structure-of-arrays store of the physics state (positions, velocities, accelerations,
+// masses). Every body lives here for its whole life, and the integrators and force
+// solvers work on these arrays in place, so a force loop only pulls the numbers it needs
+// through the cache. The colours, temperatures and magnetic fields stay in `objects`,
+// one CelestialObject per body at the same index. Adding and removing bodies goes
+// through the store so the two never get out of step
+#[derive(Debug, Clone, Default)]
+pub struct ParticleStore {
+    pub x: Vec<Real>,
+    pub y: Vec<Real>,
+    pub z: Vec<Real>,
+    pub vx: Vec<Real>,
+    pub vy: Vec<Real>,
+    pub vz: Vec<Real>,
+    pub ax: Vec<Real>,
+    pub ay: Vec<Real>,
+    pub az: Vec<Real>,
+    pub mass: Vec<Real>,
+    pub objects: Vec<CelestialObject>, // rendering and metadata
+    // Bumped every time bodies are removed. The indices after a removed body shift down,
+    // so an index kept from before a bump may now point at a different body, or past the end
+    removals: u64,
+}
+
+impl ParticleStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.is_empty()
+    }
+
+    pub fn push(&mut self, particle: Particle, object: CelestialObject) {
+        self.x.push(particle.x);
+        self.y.push(particle.y);
+        self.z.push(particle.z);
+        self.vx.push(particle.vx);
+        self.vy.push(particle.vy);
+        self.vz.push(particle.vz);
+        self.ax.push(particle.ax);
+        self.ay.push(particle.ay);
+        self.az.push(particle.az);
+        self.mass.push(particle.mass);
+        self.objects.push(object);
+    }
+
+    // The state of body i, copied out
+    pub fn particle(&self, i: usize) -> Particle {
+        Particle {
+            x: self.x[i],
+            y: self.y[i],
+            z: self.z[i],
+            vx: self.vx[i],
+            vy: self.vy[i],
+            vz: self.vz[i],
+            ax: self.ax[i],
+            ay: self.ay[i],
+            az: self.az[i],
+            mass: self.mass[i],
+        }
+    }
+
+    pub fn set_particle(&mut self, i: usize, particle: Particle) {
+        self.x[i] = particle.x;
+        self.y[i] = particle.y;
+        self.z[i] = particle.z;
+        self.vx[i] = particle.vx;
+        self.vy[i] = particle.vy;
+        self.vz[i] = particle.vz;
+        self.ax[i] = particle.ax;
+        self.ay[i] = particle.ay;
+        self.az[i] = particle.az;
+        self.mass[i] = particle.mass;
+    }
+
+    // Replace body i, state and metadata both
+    pub fn replace(&mut self, i: usize, particle: Particle, object: CelestialObject) {
+        self.set_particle(i, particle);
+        self.objects[i] = object;
+    }
+
+    // Every body's state, with its metadata
+    pub fn iter(&self) -> impl Iterator<Item = (Particle, &CelestialObject)> {
+        (0..self.len()).map(|i| (self.particle(i), &self.objects[i]))
+    }
+
+    // Keep only the bodies `keep` says yes to, given their index, in the same order
+    pub fn retain(&mut self, mut keep: impl FnMut(usize) -> bool) {
+        let kept: Vec<bool> = (0..self.len()).map(&mut keep).collect();
+        if kept.iter().all(|&k| k) {
+            return;
+        }
+        self.removals += 1;
+        for array in [&mut self.x, &mut self.y, &mut self.z, &mut self.vx, &mut self.vy, &mut self.vz,
+                      &mut self.ax, &mut self.ay, &mut self.az, &mut self.mass] {
+            let mut index = 0;
+            array.retain(|_| {
+                index += 1;
+                kept[index - 1]
+            });
+        }
+        let mut index = 0;
+        self.objects.retain(|_| {
+            index += 1;
+            kept[index - 1]
+        });
+    }
+
+    pub fn clear(&mut self) {
+        self.retain(|_| false);
+    }
+
+    // How many times bodies have been removed so far (see `removals`)
+    pub fn removals(&self) -> u64 {
+        self.removals
+    }
+
+    // Direct-sum pull of every particle on particle i, LANES particles at a time, with
+    // `softening` holding each body's share of a pair's softening length.
+    // Same rule as GravityEngine::pair_factor. Particle i itself drops out because
+    // dx = dy = dz = 0 (the unsoftened kernel returns 0 at r = 0 for that reason).
+    // The kernel is matched once out here so each inner loop is straight-line code
+    pub fn direct_acceleration_on(&self, i: usize, kernel: SofteningKernel, softening: &[Real]) -> (Real, Real, Real) {
+        match kernel {
+            SofteningKernel::Plummer => self.accelerate(i, softening, |r_sq, length| SofteningKernel::Plummer.factor(r_sq, length)),
+            SofteningKernel::Spline => self.accelerate(i, softening, |r_sq, length| SofteningKernel::Spline.factor(r_sq, length)),
+            SofteningKernel::Adaptive => self.accelerate(i, softening, |r_sq, length| SofteningKernel::Adaptive.factor(r_sq, length)),
+            SofteningKernel::None => self.accelerate(i, softening, |r_sq, length| SofteningKernel::None.factor(r_sq, length)),
+        }
+    }
+
+    #[inline(always)]
+    fn accelerate(&self, i: usize, softening: &[Real], kernel: impl Fn(Real, Real) -> Real) -> (Real, Real, Real) {
+        let xi = self.x[i];
+        let yi = self.y[i];
+        let zi = self.z[i];
+        let si = softening[i];
+
+        let mut ax: [Real; LANES] = [0.0; LANES];
+        let mut ay: [Real; LANES] = [0.0; LANES];
+        let mut az: [Real; LANES] = [0.0; LANES];
+
+        // Whole groups of LANES, then the few left over one by one
+        let whole = self.len() / LANES * LANES;
+        for start in (0..whole).step_by(LANES) {
+            let x = lanes(&self.x, start);
+            let y = lanes(&self.y, start);
+            let z = lanes(&self.z, start);
+            let mass = lanes(&self.mass, start);
+            let softening = lanes(softening, start);
+
+            for k in 0..LANES {
+                let dx = x[k] - xi;
+                let dy = y[k] - yi;
+                let dz = z[k] - zi;
+                let factor = G * mass[k] * kernel(dx * dx + dy * dy + dz * dz, si + softening[k]);
+                ax[k] += factor * dx;
+                ay[k] += factor * dy;
+                az[k] += factor * dz;
+            }
+        }
+        for (j, &softening_j) in softening.iter().enumerate().skip(whole) {
+            let dx = self.x[j] - xi;
+            let dy = self.y[j] - yi;
+            let dz = self.z[j] - zi;
+            let factor = G * self.mass[j] * kernel(dx * dx + dy * dy + dz * dz, si + softening_j);
+            ax[0] += factor * dx;
+            ay[0] += factor * dy;
+            az[0] += factor * dz;
+        }
+
+        (ax.iter().sum(), ay.iter().sum(), az.iter().sum())
+    }
+
+    // Potential per unit mass at particle i from every other particle, over the same
+    // softened pairs as direct_acceleration_on. In f64 for the energy bookkeeping
+    pub fn direct_potential_on(&self, i: usize, kernel: SofteningKernel, softening: &[Real]) -> f64 {
+        let (xi, yi, zi) = (to_f64(self.x[i]), to_f64(self.y[i]), to_f64(self.z[i]));
+        (0..self.len()).filter(|&j| j != i).map(|j| {
+            let dx = to_f64(self.x[j]) - xi;
+            let dy = to_f64(self.y[j]) - yi;
+            let dz = to_f64(self.z[j]) - zi;
+            let length = to_f64(softening[i] + softening[j]);
+            to_f64(G) * to_f64(self.mass[j]) * kernel.potential((dx * dx + dy * dy + dz * dz).sqrt(), length)
+        }).sum()
+    }
+
+    // Time derivative of direct_acceleration_on (for Hermite and block timesteps):
+    // m (factor dv + slope (d.dv) d), with slope = (d factor / dr) / r
+    pub fn direct_jerk_on(&self, i: usize, kernel: SofteningKernel, softening: &[Real]) -> (Real, Real, Real) {
+        let xi = self.x[i];
+        let yi = self.y[i];
+        let zi = self.z[i];
+        let vxi = self.vx[i];
+        let vyi = self.vy[i];
+        let vzi = self.vz[i];
+        let si = softening[i];
+
+        let mut jx: [Real; LANES] = [0.0; LANES];
+        let mut jy: [Real; LANES] = [0.0; LANES];
+        let mut jz: [Real; LANES] = [0.0; LANES];
+
+        // Pull of body j on body i, and the lane it adds into
+        let mut add = |k: usize, dx: Real, dy: Real, dz: Real, dvx: Real, dvy: Real, dvz: Real, mass: Real, length: Real| {
+            let (factor, slope) = kernel.factor_and_slope(dx * dx + dy * dy + dz * dz, length);
+            let radial = slope * (dx * dvx + dy * dvy + dz * dvz);
+            jx[k] += G * mass * (factor * dvx + radial * dx);
+            jy[k] += G * mass * (factor * dvy + radial * dy);
+            jz[k] += G * mass * (factor * dvz + radial * dz);
+        };
+
+        let whole = self.len() / LANES * LANES;
+        for start in (0..whole).step_by(LANES) {
+            let x = lanes(&self.x, start);
+            let y = lanes(&self.y, start);
+            let z = lanes(&self.z, start);
+            let vx = lanes(&self.vx, start);
+            let vy = lanes(&self.vy, start);
+            let vz = lanes(&self.vz, start);
+            let mass = lanes(&self.mass, start);
+            let softening = lanes(softening, start);
+
+            for k in 0..LANES {
+                add(k, x[k] - xi, y[k] - yi, z[k] - zi, vx[k] - vxi, vy[k] - vyi, vz[k] - vzi,
+                    mass[k], si + softening[k]);
+            }
+        }
+        for (j, &softening_j) in softening.iter().enumerate().skip(whole) {
+            add(0, self.x[j] - xi, self.y[j] - yi, self.z[j] - zi, self.vx[j] - vxi, self.vy[j] - vyi,
+                self.vz[j] - vzi, self.mass[j], si + softening_j);
+        }
+
+        (jx.iter().sum(), jy.iter().sum(), jz.iter().sum())
+    }
+}
+
+impl FromIterator<Body> for ParticleStore {
+    fn from_iter<I: IntoIterator<Item = Body>>(bodies: I) -> Self {
+        let mut store = ParticleStore::new();
+        store.extend(bodies);
+        store
+    }
+}
+
+impl Extend<Body> for ParticleStore {
+    fn extend<I: IntoIterator<Item = Body>>(&mut self, bodies: I) {
+        for (particle, object) in bodies {
+            self.push(particle, object);
+        }
+    }
+}
+
+// The LANES values starting at `start`, as a fixed-size array so the compiler can see
+// the loop length (and skip bounds checks) when vectorizing
+fn lanes(values: &[Real], start: usize) -> &[Real; LANES] {
+    values[start..start + LANES].try_into().unwrap()
+}
@@ -1,42 +1,765 @@
-use crate::objects::CelestialObject;
-use crate::gravity::GravityEngine;
+use std::cell::Cell;
+use crate::particles::ParticleStore;
+use crate::gravity::{gather, GravityEngine};
+use crate::force_field::ForceField;
+use crate::real::Real;
+
+// Everything that accelerates objects: gravity, then any registered force fields.
+// Integrators call this as many times per step as their scheme needs, and it keeps count
+// so schemes can be compared by cost
+pub struct ForceModel<'a> {
+    pub gravity: &'a GravityEngine,
+    pub fields: &'a [Box<dyn ForceField>],
+    evaluations: Cell<u32>,
+}
+
+impl<'a> ForceModel<'a> {
+    pub fn new(gravity: &'a GravityEngine, fields: &'a [Box<dyn ForceField>]) -> Self {
+        ForceModel {
+            gravity,
+            fields,
+            evaluations: Cell::new(0),
+        }
+    }
+    
+    // Fill ax/ay/az for the current positions (and velocities)
+    pub fn accelerations(&self, particles: &mut ParticleStore) {
+        self.evaluations.set(self.evaluations.get() + 1);
+        self.gravity.calculate_gravitational_forces(particles);
+        let all: Vec<usize> = (0..particles.len()).collect();
+        self.add_fields(particles, &all);
+    }
+    
+    // Only refresh ax/ay/az for some objects (block timesteps)
+    pub fn accelerations_for(&self, particles: &mut ParticleStore, active: &[usize]) {
+        self.evaluations.set(self.evaluations.get() + 1);
+        self.gravity.calculate_forces_for(particles, active);
+        self.add_fields(particles, active);
+    }
+    
+    // Every field sees the objects where gravity left them, before any field has pushed
+    fn add_fields(&self, particles: &mut ParticleStore, active: &[usize]) {
+        if self.fields.is_empty() {
+            return;
+        }
+        let store: &ParticleStore = particles;
+        let extra = gather(active, |i| {
+            self.fields.iter().fold((0.0, 0.0, 0.0), |sum, field| {
+                let (ax, ay, az) = field.acceleration_on(i, store);
+                (sum.0 + ax, sum.1 + ay, sum.2 + az)
+            })
+        });
+        for (&i, (ax, ay, az)) in active.iter().zip(extra) {
+            particles.ax[i] += ax;
+            particles.ay[i] += ay;
+            particles.az[i] += az;
+        }
+    }
+    
+    // Time derivative of the accelerations, for schemes like Hermite
+    pub fn jerks(&self, particles: &ParticleStore) -> Vec<(Real, Real, Real)> {
+        let jerks = self.gravity.calculate_jerks(particles);
+        if self.fields.is_empty() {
+            return jerks;
+        }
+        let all: Vec<usize> = (0..particles.len()).collect();
+        gather(&all, |i| {
+            self.fields.iter().fold(jerks[i], |sum, field| {
+                let (jx, jy, jz) = field.jerk_on(i, particles);
+                (sum.0 + jx, sum.1 + jy, sum.2 + jz)
+            })
+        })
+    }
+    
+    pub fn evaluations(&self) -> u32 {
+        self.evaluations.get()
+    }
+}
+
+// A time integration scheme. On entry ax/ay/az hold the accelerations at the current
+// positions, and the step has to leave them that way for the new positions
+pub trait Integrator {
+    fn name(&self) -> &'static str;
+    fn step(&mut self, particles: &mut ParticleStore, forces: &ForceModel, dt: Real);
+}
+
+// The schemes the I key cycles through, cheapest first
+pub fn builtin_integrators() -> Vec<Box<dyn Integrator>> {
+    vec![
+        Box::new(Euler),
+        Box::new(Leapfrog),
+        Box::new(RungeKutta4),
+        Box::new(Yoshida4),
+        Box::new(Hermite4),
+        Box::new(BlockLeapfrog::new()),
+    ]
+}
+
+// First order semi-implicit Euler: kick with the old force, then drift with the new speed.
+// Cheap but it slowly leaks energy
+pub struct Euler;
+
+impl Integrator for Euler {
+    fn name(&self) -> &'static str {
+        "Euler"
+    }
+    
+    fn step(&mut self, particles: &mut ParticleStore, forces: &ForceModel, dt: Real) {
+        kick(particles, dt);
+        drift(particles, dt);
+        forces.accelerations(particles);
+    }
+}
+
+// Kick-drift-kick leapfrog: half a velocity kick, a full position drift, new forces,
+// then the second half kick. Second order and symplectic, so orbits keep their energy
+pub struct Leapfrog;
+
+impl Integrator for Leapfrog {
+    fn name(&self) -> &'static str {
+        "Leapfrog"
+    }
+    
+    fn step(&mut self, particles: &mut ParticleStore, forces: &ForceModel, dt: Real) {
+        kick(particles, dt * 0.5);
+        drift(particles, dt);
+        forces.accelerations(particles);
+        kick(particles, dt * 0.5);
+    }
+}
+
+// Classic fourth order Runge-Kutta. Very accurate per step, but not symplectic
+// and it costs four force evaluations
+pub struct RungeKutta4;
+
+impl Integrator for RungeKutta4 {
+    fn name(&self) -> &'static str {
+        "RK4"
+    }
+    
+    fn step(&mut self, particles: &mut ParticleStore, forces: &ForceModel, dt: Real) {
+        let p = &*particles;
+        let start: Vec<[Real; 6]> = (0..p.len()).map(|i| [p.x[i], p.y[i], p.z[i], p.vx[i], p.vy[i], p.vz[i]]).collect();
+        
+        // Each stage is (velocity, acceleration) = derivative of (position, velocity)
+        let derivatives = |p: &ParticleStore| -> Vec<[Real; 6]> {
+            (0..p.len()).map(|i| [p.vx[i], p.vy[i], p.vz[i], p.ax[i], p.ay[i], p.az[i]]).collect()
+        };
+        let mut stages: Vec<Vec<[Real; 6]>> = vec![derivatives(particles)];
+        
+        for k in 1..4 {
+            // The last stage looks a full step ahead, the middle two half a step
+            let h = if k == 3 { dt } else { dt * 0.5 };
+            for (i, (s, d)) in start.iter().zip(&stages[k - 1]).enumerate() {
+                particles.x[i] = s[0] + d[0] * h;
+                particles.y[i] = s[1] + d[1] * h;
+                particles.z[i] = s[2] + d[2] * h;
+                particles.vx[i] = s[3] + d[3] * h;
+                particles.vy[i] = s[4] + d[4] * h;
+                particles.vz[i] = s[5] + d[5] * h;
+            }
+            forces.accelerations(particles);
+            stages.push(derivatives(particles));
+        }
+        
+        for (i, s) in start.iter().enumerate() {
+            let weighted = |c: usize| (stages[0][i][c] + 2.0 * stages[1][i][c] + 2.0 * stages[2][i][c] + stages[3][i][c]) / 6.0;
+            particles.x[i] = s[0] + weighted(0) * dt;
+            particles.y[i] = s[1] + weighted(1) * dt;
+            particles.z[i] = s[2] + weighted(2) * dt;
+            particles.vx[i] = s[3] + weighted(3) * dt;
+            particles.vy[i] = s[4] + weighted(4) * dt;
+            particles.vz[i] = s[5] + weighted(5) * dt;
+        }
+        forces.accelerations(particles);
+    }
+}
+
+// Yoshida's fourth order symplectic scheme: three leapfrog steps of w1, w0, w1 times dt,
+// where the middle one runs backwards in time and cancels the leapfrog's error
+pub struct Yoshida4;
+
+impl Integrator for Yoshida4 {
+    fn name(&self) -> &'static str {
+        "Yoshida 4"
+    }
+    
+    fn step(&mut self, particles: &mut ParticleStore, forces: &ForceModel, dt: Real) {
+        let cbrt2 = Real::cbrt(2.0);
+        let w1 = 1.0 / (2.0 - cbrt2);
+        let w0 = -cbrt2 / (2.0 - cbrt2);
+        
+        for w in [w1, w0, w1] {
+            Leapfrog.step(particles, forces, w * dt);
+        }
+    }
+}
+
+// Fourth order Hermite predictor-corrector (the workhorse of direct N-body codes).
+// Predicts with the jerk, evaluates forces at the prediction, then corrects using both
+// ends of the step. A last evaluation at the corrected positions keeps the Integrator
+// contract, so two force evaluations per step in all. The jerks come through the active
+// solver too: exact under the direct sum, a Barnes-Hut walk otherwise, so a step costs
+// O(n log n) under the fast solvers. Fourth order only holds for the direct sum; with an
+// approximate force the scheme is only as good as the force
+pub struct Hermite4;
+
+impl Integrator for Hermite4 {
+    fn name(&self) -> &'static str {
+        "Hermite 4"
+    }
+    
+    fn step(&mut self, particles: &mut ParticleStore, forces: &ForceModel, dt: Real) {
+        // Position, velocity and acceleration at the start, one (x, y, z) triple each
+        let q = &*particles;
+        let start: Vec<[[Real; 3]; 3]> = (0..q.len())
+            .map(|i| [[q.x[i], q.y[i], q.z[i]], [q.vx[i], q.vy[i], q.vz[i]], [q.ax[i], q.ay[i], q.az[i]]])
+            .collect();
+        let start_jerks: Vec<[Real; 3]> = forces.jerks(particles).into_iter().map(|(x, y, z)| [x, y, z]).collect();
+        
+        // Predict with a Taylor series up to the jerk
+        let dt2 = dt * dt / 2.0;
+        let dt3 = dt * dt * dt / 6.0;
+        for (i, ([p, v, a], j)) in start.iter().zip(&start_jerks).enumerate() {
+            let position = |c: usize| p[c] + v[c] * dt + a[c] * dt2 + j[c] * dt3;
+            let velocity = |c: usize| v[c] + a[c] * dt + j[c] * dt2;
+            (particles.x[i], particles.y[i], particles.z[i]) = (position(0), position(1), position(2));
+            (particles.vx[i], particles.vy[i], particles.vz[i]) = (velocity(0), velocity(1), velocity(2));
+        }
+        
+        forces.accelerations(particles);
+        let end_jerks: Vec<[Real; 3]> = forces.jerks(particles).into_iter().map(|(x, y, z)| [x, y, z]).collect();
+        
+        // Correct
+        let dt12 = dt * dt / 12.0;
+        for (i, (([p, v, a], j0), j1)) in start.iter().zip(&start_jerks).zip(&end_jerks).enumerate() {
+            let a1 = [particles.ax[i], particles.ay[i], particles.az[i]];
+            let velocity = |c: usize| v[c] + (a[c] + a1[c]) * dt / 2.0 + (j0[c] - j1[c]) * dt12;
+            let new_v = [velocity(0), velocity(1), velocity(2)];
+            let position = |c: usize| p[c] + (v[c] + new_v[c]) * dt / 2.0 + (a[c] - a1[c]) * dt12;
+            (particles.x[i], particles.y[i], particles.z[i]) = (position(0), position(1), position(2));
+            (particles.vx[i], particles.vy[i], particles.vz[i]) = (new_v[0], new_v[1], new_v[2]);
+        }
+        
+        // ax/ay/az are still the forces at the predicted positions
+        forces.accelerations(particles);
+    }
+}
+
+// Leapfrog with hierarchical block timesteps. Every object gets its own step dt / 2^level,
+// picked from its acceleration and jerk, so a planet skimming its star takes many small
+// steps while a comet far out takes one big one. Levels are powers of two, so all
+// objects line up again at the end of the full step
+pub struct BlockLeapfrog {
+    pub max_level: u32, // finest step is dt / 2^max_level
+    pub eta: Real,       // accuracy parameter - smaller means smaller steps
+}
+
+impl BlockLeapfrog {
+    pub fn new() -> Self {
+        BlockLeapfrog {
+            max_level: 8,
+            eta: 0.02,
+        }
+    }
+    
+    // Aarseth's criterion sqrt(|a| / |jerk|), plus a plain acceleration limit
+    // sqrt(softening / |a|) for objects whose jerk happens to be tiny. The jerks cost what
+    // the active solver's forces do (see GravityEngine::calculate_jerks)
+    fn choose_levels(&self, particles: &ParticleStore, forces: &ForceModel, dt: Real) -> Vec<u32> {
+        let jerks = forces.jerks(particles);
+        let softening = forces.gravity.softening_length;
+        let p = particles;
+        
+        jerks.into_iter().enumerate().map(|(i, (jx, jy, jz))| {
+            let acc = (p.ax[i] * p.ax[i] + p.ay[i] * p.ay[i] + p.az[i] * p.az[i]).sqrt();
+            let jerk = (jx * jx + jy * jy + jz * jz).sqrt();
+            
+            let mut wanted = Real::MAX;
+            if jerk > 0.0 {
+                wanted = wanted.min(self.eta * (acc / jerk).sqrt());
+            }
+            if acc > 0.0 {
+                wanted = wanted.min((self.eta * softening / acc).sqrt());
+            }
+            
+            if wanted >= dt {
+                0
+            } else {
+                ((dt / wanted).log2().ceil() as u32).min(self.max_level)
+            }
+        }).collect()
+    }
+}
+
+impl Default for BlockLeapfrog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Integrator for BlockLeapfrog {
+    fn name(&self) -> &'static str {
+        "Block Leapfrog"
+    }
+    
+    fn step(&mut self, particles: &mut ParticleStore, forces: &ForceModel, dt: Real) {
+        let levels = self.choose_levels(particles, forces, dt);
+        let finest = levels.iter().copied().max().unwrap_or(0);
+        let substeps = 1usize << finest;
+        let tiny_dt = dt / substeps as Real;
+        
+        // How many of the finest substeps each object's own step spans
+        let spans: Vec<usize> = levels.iter().map(|&level| 1usize << (finest - level)).collect();
+        
+        for sub in 0..substeps {
+            // Opening half kick for objects starting a step
+            for (i, &span) in spans.iter().enumerate() {
+                if sub % span == 0 {
+                    let own_dt = tiny_dt * span as Real;
+                    particles.vx[i] += particles.ax[i] * own_dt * 0.5;
+                    particles.vy[i] += particles.ay[i] * own_dt * 0.5;
+                    particles.vz[i] += particles.az[i] * own_dt * 0.5;
+                }
+            }
+            
+            // Everyone drifts every substep so positions stay in sync
+            drift(particles, tiny_dt);
+            
+            // New forces and the closing half kick for objects finishing a step
+            let finishing: Vec<usize> = (0..particles.len()).filter(|&i| (sub + 1) % spans[i] == 0).collect();
+            forces.accelerations_for(particles, &finishing);
+            for &i in &finishing {
+                let own_dt = tiny_dt * spans[i] as Real;
+                particles.vx[i] += particles.ax[i] * own_dt * 0.5;
+                particles.vy[i] += particles.ay[i] * own_dt * 0.5;
+                particles.vz[i] += particles.az[i] * own_dt * 0.5;
+            }
+        }
+    }
+}
+
+// ax/ay/az are already accelerations (the gravity engine multiplies by the other mass only)
+fn kick(particles: &mut ParticleStore, dt: Real) {
+    for (v, a) in [(&mut particles.vx, &particles.ax), (&mut particles.vy, &particles.ay), (&mut particles.vz, &particles.az)] {
+        for (v, a) in v.iter_mut().zip(a) {
+            *v += a * dt;
+        }
+    }
+}
+
+fn drift(particles: &mut ParticleStore, dt: Real) {
+    for (x, v) in [(&mut particles.x, &particles.vx), (&mut particles.y, &particles.vy), (&mut particles.z, &particles.vz)] {
+        for (x, v) in x.iter_mut().zip(v) {
+            *x += v * dt;
+        }
+    }
+}
+
+// What to do about objects that get too fast. Nothing in Newtonian gravity stops a
+// speed from growing without bound in a close encounter
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VelocityPolicy {
+    Off,          // any speed goes, and momentum and energy are kept
+    Clamp,        // speeds past max_speed are scaled back down to it, and the event log says so
+    Relativistic, // kicks change the momentum γv, so speeds creep up on c but never reach it
+}
+
+impl VelocityPolicy {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VelocityPolicy::Off => "Off",
+            VelocityPolicy::Clamp => "Clamp",
+            VelocityPolicy::Relativistic => "Relativistic",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            VelocityPolicy::Off => VelocityPolicy::Clamp,
+            VelocityPolicy::Clamp => VelocityPolicy::Relativistic,
+            VelocityPolicy::Relativistic => VelocityPolicy::Off,
+        }
+    }
+}
+
+// Closest a speed from before the step may come to c, as (v/c)², so γ stays finite
+const MAX_BETA_SQ: Real = 0.999999;
 
 pub struct PhysicsEngine {
     pub gravity_engine: GravityEngine,
-    pub max_speed: f32,
+    pub integrator: Box<dyn Integrator>,
+    pub velocity_policy: VelocityPolicy,
+    pub max_speed: Real, // the Clamp limit. Relativistic uses the gravity engine's speed_of_light
+    pub force_evaluations: u32, // how many times the last step had to compute forces
+    // Extra physics on top of gravity, applied in the order added
+    pub force_fields: Vec<Box<dyn ForceField>>,
+    // Objects the velocity policy stepped in for during the last step: clamped, or for
+    // Relativistic, ones the Newtonian kick would have sent to c or past it
+    pub speed_limited: u32,
+    pub fastest_limited: Option<(usize, Real)>, // index, and the speed the step would have given it
+    accelerations_ready: bool, // ax/ay/az still hold the forces from the end of the last step
 }
 
 impl PhysicsEngine {
     pub fn new() -> Self {
         PhysicsEngine {
             gravity_engine: GravityEngine::new(),
+            integrator: Box::new(Leapfrog),
+            velocity_policy: VelocityPolicy::Clamp,
             max_speed: 100.0, //limit
+            force_evaluations: 0,
+            force_fields: Vec::new(),
+            speed_limited: 0,
+            fastest_limited: None,
+            accelerations_ready: false,
         }
     }
     
-    pub fn update_physics(&mut self, objects: &mut [CelestialObject], time_scale: f32) {
-        // calculate gravity
-        self.gravity_engine.calculate_gravitational_forces(objects);
-        
-        // 
-        for obj in objects.iter_mut() {
-            // convert (F = ma => a = F/m)
-            if obj.mass > 0.0 {
-                let ax = obj.ax / obj.mass;
-                let ay = obj.ay / obj.mass;
-                
-                obj.vx += ax * time_scale * self.gravity_engine.time_step_factor;
-                obj.vy += ay * time_scale * self.gravity_engine.time_step_factor;
-                
-               // let speed: f32 = (obj.vx * obj.vx + obj.vy * obj.vy);
-
-               //
-                let speed: f32 = (obj.vx * obj.vx + obj.vy * obj.vy).sqrt();
-                if speed > self.max_speed {
-                    obj.vx = obj.vx / speed * self.max_speed;
-                    obj.vy = obj.vy / speed * self.max_speed;
-                }
+    // Move on to the next built-in scheme (bound to the I key)
+    pub fn cycle_integrator(&mut self) {
+        let mut schemes = builtin_integrators();
+        let current = schemes.iter().position(|s| s.name() == self.integrator.name());
+        let next = current.map_or(0, |i| (i + 1) % schemes.len());
+        self.integrator = schemes.swap_remove(next);
+    }
+    
+    // Length of one physics step for the given time scale
+    pub fn time_step(&self, time_scale: Real) -> Real {
+        time_scale * self.gravity_engine.time_step_factor
+    }
+    
+    // Add a force that acts from the next step on
+    pub fn add_force_field(&mut self, field: impl ForceField + 'static) {
+        self.force_fields.push(Box::new(field));
+        self.invalidate_forces();
+    }
+    
+    // Drop the force fields called `name`. Returns true if there were any
+    pub fn remove_force_field(&mut self, name: &str) -> bool {
+        let before = self.force_fields.len();
+        self.force_fields.retain(|field| field.name() != name);
+        let removed = self.force_fields.len() != before;
+        if removed {
+            self.invalidate_forces();
+        }
+        removed
+    }
+    
+    // Drop every registered force field
+    pub fn clear_force_fields(&mut self) {
+        self.force_fields.clear();
+        self.invalidate_forces();
+    }
+    
+    // Objects were added, removed or merged, so the forces left over from the last step
+    // no longer match them
+    pub fn invalidate_forces(&mut self) {
+        self.accelerations_ready = false;
+    }
+    
+    // Let every force field look at the bodies as they are now (see ForceField::prepare)
+    pub fn prepare_force_fields(&mut self, particles: &ParticleStore) {
+        for field in &mut self.force_fields {
+            field.prepare(particles);
+        }
+    }
+    
+    pub fn update_physics(&mut self, particles: &mut ParticleStore, time_scale: Real) {
+        let dt = self.time_step(time_scale);
+        self.prepare_force_fields(particles);
+        let forces = ForceModel::new(&self.gravity_engine, &self.force_fields);
+        
+        // The first step has nothing to reuse yet
+        if !self.accelerations_ready {
+            forces.accelerations(particles);
+            self.accelerations_ready = true;
+        }
+        
+        // The relativistic policy needs to know how much the step changed each velocity
+        let start: Vec<(Real, Real, Real)> = if self.velocity_policy == VelocityPolicy::Relativistic {
+            (0..particles.len()).map(|i| (particles.vx[i], particles.vy[i], particles.vz[i])).collect()
+        } else {
+            Vec::new()
+        };
+        
+        self.integrator.step(particles, &forces, dt);
+        self.force_evaluations = forces.evaluations();
+        
+        self.speed_limited = 0;
+        self.fastest_limited = None;
+        match self.velocity_policy {
+            VelocityPolicy::Off => {}
+            VelocityPolicy::Clamp => self.clamp_speeds(particles),
+            VelocityPolicy::Relativistic => self.relativistic_speeds(particles, &start, dt),
+        }
+    }
+    
+    fn note_limited(&mut self, index: usize, speed: Real) {
+        self.speed_limited += 1;
+        if self.fastest_limited.is_none_or(|(_, fastest)| speed > fastest) {
+            self.fastest_limited = Some((index, speed));
+        }
+    }
+    
+    // Scale anything faster than max_speed back down to it. This throws momentum and
+    // energy away, which is why it gets counted
+    fn clamp_speeds(&mut self, particles: &mut ParticleStore) {
+        let max_speed = self.max_speed;
+        for i in 0..particles.len() {
+            let (vx, vy, vz) = (particles.vx[i], particles.vy[i], particles.vz[i]);
+            let speed: Real = (vx * vx + vy * vy + vz * vz).sqrt();
+            if speed > max_speed {
+                particles.vx[i] = vx / speed * max_speed;
+                particles.vy[i] = vy / speed * max_speed;
+                particles.vz[i] = vz / speed * max_speed;
+                self.note_limited(i, speed);
+            }
+        }
+    }
+    
+    // Treat the velocity change the integrator worked out as an impulse per unit rest mass
+    // and add it to the proper velocity u = γv instead of to v. Then v = u / √(1 + u²/c²)
+    // stays below c however hard the kick, and a fast object turns and speeds up less for
+    // the same force, as it would in special relativity. The drift used the Newtonian
+    // velocity, so positions get half the velocity correction over the step as well. That
+    // nudge is small enough to leave the forces from the end of the step standing
+    fn relativistic_speeds(&mut self, particles: &mut ParticleStore, start: &[(Real, Real, Real)], dt: Real) {
+        let c = self.gravity_engine.speed_of_light;
+        let c_sq = c * c;
+        for (i, &(vx0, vy0, vz0)) in start.iter().enumerate() {
+            let (nx, ny, nz) = (particles.vx[i], particles.vy[i], particles.vz[i]);
+            // A speed at or past c left over from another policy counts as just under it
+            let beta_sq = ((vx0 * vx0 + vy0 * vy0 + vz0 * vz0) / c_sq).min(MAX_BETA_SQ);
+            let gamma = 1.0 / (1.0 - beta_sq).sqrt();
+            let ux = vx0 * gamma + (nx - vx0);
+            let uy = vy0 * gamma + (ny - vy0);
+            let uz = vz0 * gamma + (nz - vz0);
+            
+            let newtonian_sq = nx * nx + ny * ny + nz * nz;
+            let new_gamma = (1.0 + (ux * ux + uy * uy + uz * uz) / c_sq).sqrt();
+            let (vx, vy, vz) = (ux / new_gamma, uy / new_gamma, uz / new_gamma);
+            
+            particles.x[i] += (vx - nx) * dt * 0.5;
+            particles.y[i] += (vy - ny) * dt * 0.5;
+            particles.z[i] += (vz - nz) * dt * 0.5;
+            particles.vx[i] = vx;
+            particles.vy[i] = vy;
+            particles.vz[i] = vz;
+            if newtonian_sq >= c_sq {
+                self.note_limited(i, newtonian_sq.sqrt());
             }
         }
     }
-}
\ No newline at end of file
+}
+
+impl Default for PhysicsEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics::Diagnostics;
+    use crate::force_field::UniformField;
+    use crate::gravity::{SofteningKernel, G};
+    use crate::objects::{CelestialObject, ObjectType};
+    use crate::particles::Particle;
+    use crate::real::to_f64;
+    use std::f64::consts::PI;
+
+    // Bare Newtonian gravity, so the orbit is a true Kepler ellipse
+    fn newtonian() -> GravityEngine {
+        let mut gravity = GravityEngine::new();
+        gravity.softening_kernel = SofteningKernel::None;
+        gravity.softening_length = 1e-3;
+        gravity
+    }
+
+    // A light body on an orbit with semi-major axis 10 and eccentricity e around a 10⁶
+    // mass, starting at apastron, with the centre of mass at rest. Returns the period too
+    fn kepler(e: f64) -> (ParticleStore, f64) {
+        let (a, mass) = (10.0, 1e6);
+        let gm = to_f64(G) * (mass + 1.0);
+        let speed = (gm / a * (1.0 - e) / (1.0 + e)).sqrt();
+        let particles = [
+            (Particle::new(0.0, 0.0, 0.0, -(speed / mass) as Real, mass as Real),
+             CelestialObject::new(1.0, mass as Real, ObjectType::Star, 0.0)),
+            (Particle::new((a * (1.0 + e)) as Real, 0.0, 0.0, speed as Real, 1.0),
+             CelestialObject::new(0.1, 1.0, ObjectType::Planet, 0.0)),
+        ].into_iter().collect();
+        (particles, 2.0 * PI * (a * a * a / gm).sqrt())
+    }
+
+    fn energy(particles: &ParticleStore) -> f64 {
+        let p = particles;
+        let kinetic: f64 = (0..p.len()).map(|i| {
+            let (vx, vy, vz) = (to_f64(p.vx[i]), to_f64(p.vy[i]), to_f64(p.vz[i]));
+            0.5 * to_f64(p.mass[i]) * (vx * vx + vy * vy + vz * vz)
+        }).sum();
+        let (dx, dy, dz) = (to_f64(p.x[1] - p.x[0]), to_f64(p.y[1] - p.y[0]), to_f64(p.z[1] - p.z[0]));
+        kinetic - to_f64(G) * to_f64(p.mass[0]) * to_f64(p.mass[1]) / (dx * dx + dy * dy + dz * dz).sqrt()
+    }
+
+    // Take `steps` steps of dt, calling `each` after every one
+    fn run(integrator: &mut dyn Integrator, particles: &mut ParticleStore, gravity: &GravityEngine, dt: f64,
+           steps: usize, mut each: impl FnMut(&ParticleStore)) {
+        let forces = ForceModel::new(gravity, &[]);
+        forces.accelerations(particles);
+        for _ in 0..steps {
+            integrator.step(particles, &forces, dt as Real);
+            each(particles);
+        }
+    }
+
+    // Leapfrog is symplectic, so its energy error on a Kepler orbit swings within the same
+    // band orbit after orbit instead of growing with time
+    #[test]
+    fn leapfrog_energy_error_does_not_grow() {
+        let (mut particles, period) = kepler(0.5);
+        let start = energy(&particles);
+        let mut worst = [0.0; 10]; // per orbit
+        let mut step = 0;
+        run(&mut Leapfrog, &mut particles, &newtonian(), period / 400.0, 4000, |p| {
+            let orbit = step / 400;
+            worst[orbit] = f64::max(worst[orbit], ((energy(p) - start) / start).abs());
+            step += 1;
+        });
+        assert!(worst[9] < 1.5 * worst[0], "energy error grew from {:e} to {:e}", worst[0], worst[9]);
+    }
+
+    // Ten orbits at e = 0.5 and 400 steps per orbit. The symplectic schemes keep the
+    // energy error bounded; RK4 and Hermite drift, but slowly at this step
+    #[test]
+    fn kepler_energy_drift_is_bounded() {
+        let gravity = newtonian();
+        for mut integrator in builtin_integrators() {
+            let bound = match integrator.name() {
+                "Euler" => 5e-2,
+                "Leapfrog" => 2e-3,
+                "Block Leapfrog" => 2e-4,
+                _ => 1e-4,
+            };
+            let (mut particles, period) = kepler(0.5);
+            let start = energy(&particles);
+            let mut worst: f64 = 0.0;
+            run(integrator.as_mut(), &mut particles, &gravity, period / 400.0, 4000, |p| {
+                worst = worst.max(((energy(p) - start) / start).abs());
+            });
+            assert!(worst < bound, "{}: energy drift {worst:e}, over {bound:e}", integrator.name());
+        }
+    }
+
+    // How far the light body is from where it started after one orbit in n steps
+    fn return_error(integrator: &mut dyn Integrator, n: usize) -> f64 {
+        let (mut particles, period) = kepler(0.5);
+        let start = (to_f64(particles.x[1]), to_f64(particles.y[1]));
+        run(integrator, &mut particles, &newtonian(), period / n as f64, n, |_| {});
+        (to_f64(particles.x[1]) - start.0).hypot(to_f64(particles.y[1]) - start.1)
+    }
+
+    // Halving the step should cut the error after one orbit by about 2⁴. Coarse enough
+    // steps that f32 round-off stays well below the error
+    #[test]
+    fn yoshida_and_hermite_converge_at_fourth_order() {
+        for integrator in [&mut Yoshida4 as &mut dyn Integrator, &mut Hermite4] {
+            let errors: Vec<f64> = [50, 100, 200].into_iter().map(|n| return_error(integrator, n)).collect();
+            for pair in errors.windows(2) {
+                let order = (pair[0] / pair[1]).log2();
+                assert!((3.5..4.5).contains(&order), "{}: order {order:.2} from errors {errors:?}", integrator.name());
+            }
+        }
+    }
+
+    // Two equal stars on a circular orbit feel the same |a| and |jerk|, so block timesteps
+    // put both on the same level and must then take the same steps as plain leapfrog
+    #[test]
+    fn block_leapfrog_matches_leapfrog_on_one_level() {
+        let gravity = newtonian();
+        let (mass, r) = (1e6, 5.0);
+        let speed = (to_f64(G) * mass * r).sqrt() / (2.0 * r);
+        let binary: ParticleStore = [
+            (Particle::new(-r as Real, 0.0, 0.0, -speed as Real, mass as Real),
+             CelestialObject::new(1.0, mass as Real, ObjectType::Star, 0.0)),
+            (Particle::new(r as Real, 0.0, 0.0, speed as Real, mass as Real),
+             CelestialObject::new(1.0, mass as Real, ObjectType::Star, 0.0)),
+        ].into_iter().collect();
+        let dt = 0.1;
+
+        let mut block = BlockLeapfrog::new();
+        let mut with_forces = binary.clone();
+        let forces = ForceModel::new(&gravity, &[]);
+        forces.accelerations(&mut with_forces);
+        let levels = block.choose_levels(&with_forces, &forces, dt as Real);
+        assert!(levels[0] > 0 && levels[0] == levels[1], "levels {levels:?}");
+        let substeps = 1usize << levels[0];
+
+        let mut blocked = binary.clone();
+        run(&mut block, &mut blocked, &gravity, dt, 20, |_| {});
+        let mut plain = binary;
+        run(&mut Leapfrog, &mut plain, &gravity, dt / substeps as f64, 20 * substeps, |_| {});
+
+        for i in 0..2 {
+            let (bx, by) = (to_f64(blocked.x[i]), to_f64(blocked.y[i]));
+            let (px, py) = (to_f64(plain.x[i]), to_f64(plain.y[i]));
+            assert!((bx - px).hypot(by - py) < 1e-4 * r, "body {i}: ({bx}, {by}) vs ({px}, {py})");
+        }
+    }
+
+    // One body alone, so gravity adds nothing and only the field pushes it
+    fn pushed(policy: VelocityPolicy, push: Real) -> (PhysicsEngine, ParticleStore) {
+        let mut engine = PhysicsEngine::new();
+        engine.velocity_policy = policy;
+        engine.add_force_field(UniformField { acceleration: (push, 0.5 * push, 0.0) });
+        let particles = [(Particle::new(0.0, 0.0, 0.0, 0.0, 1.0), CelestialObject::new(1.0, 1.0, ObjectType::Planet, 0.0))]
+            .into_iter().collect();
+        (engine, particles)
+    }
+
+    fn speed(particles: &ParticleStore) -> Real {
+        (particles.vx[0] * particles.vx[0] + particles.vy[0] * particles.vy[0] + particles.vz[0] * particles.vz[0]).sqrt()
+    }
+
+    // Kicks worth many times c per step still leave the body just under c
+    #[test]
+    fn relativistic_policy_stays_below_c() {
+        let (mut engine, mut particles) = pushed(VelocityPolicy::Relativistic, 1e5);
+        let c = engine.gravity_engine.speed_of_light;
+        for step in 0..50 {
+            engine.update_physics(&mut particles, 1.0);
+            assert!(speed(&particles) < c, "step {step}: speed {} with c = {c}", speed(&particles));
+            assert_eq!(engine.speed_limited, 1);
+        }
+        assert!(speed(&particles) > 0.99 * c);
+    }
+
+    // A kick well short of c is left Newtonian to first order, and doesn't count
+    #[test]
+    fn relativistic_policy_leaves_slow_bodies_alone() {
+        let (mut engine, mut particles) = pushed(VelocityPolicy::Relativistic, 1.0);
+        engine.update_physics(&mut particles, 1.0);
+        let dt = engine.time_step(1.0);
+        let newtonian = 1.0 * dt * (1.25 as Real).sqrt();
+        assert!((speed(&particles) - newtonian).abs() < 1e-3 * newtonian);
+        assert_eq!(engine.speed_limited, 0);
+    }
+
+    // Each clamp is counted by the engine for the step and added up by the diagnostics
+    #[test]
+    fn clamp_caps_the_speed_and_counts_for_the_diagnostics() {
+        let (mut engine, mut particles) = pushed(VelocityPolicy::Clamp, 1e4);
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.reset(&particles, &mut engine);
+        for step in 1..=3 {
+            engine.update_physics(&mut particles, 1.0);
+            let dt = engine.time_step(1.0);
+            diagnostics.record(&particles, &mut engine, dt);
+            assert!((speed(&particles) - engine.max_speed).abs() < 1e-3 * engine.max_speed);
+            assert_eq!(engine.speed_limited, 1);
+            assert_eq!(diagnostics.speed_limited, step);
+        }
+        assert_eq!(engine.fastest_limited.map(|(index, _)| index), Some(0));
+    }
+}
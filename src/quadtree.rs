@@ -0,0 +1,323 @@
+use crate::gravity::{GravityEngine, G};
+use crate::particles::ParticleStore;
+use crate::real::{to_f64, Real};
+
+// Bodies per leaf before a node gets split into four (or eight) children
+const LEAF_CAPACITY: usize = 8;
+// Stop splitting here, so piles of bodies sitting on the same spot can't recurse forever
+const MAX_DEPTH: usize = 24;
+
+// One square (cube, in 3D) cell of the tree. Children are stored as consecutive nodes
+struct Node {
+    center_x: Real,
+    center_y: Real,
+    center_z: Real,
+    half_size: Real,
+    mass: Real,
+    com_x: Real, // centre of mass
+    com_y: Real,
+    com_z: Real,
+    vel_x: Real, // velocity of the centre of mass, for the jerk
+    vel_y: Real,
+    vel_z: Real,
+    first_child: usize, // 0 = leaf (the root can never be a child)
+    start: usize,       // range of bodies in `order` that live under this node
+    count: usize,
+}
+
+// Barnes-Hut quadtree - every node knows the total mass and centre of mass of
+// the bodies below it, so a far away clump of stars can be treated as one big body.
+// As soon as anything is off the z = 0 plane it becomes an octree: cells are cubes
+// and split into eight
+pub struct QuadTree {
+    nodes: Vec<Node>,
+    order: Vec<usize>, // body indices, grouped so every node owns a contiguous slice
+    children: usize,   // 4 for a flat scene, 8 in 3D
+}
+
+impl QuadTree {
+    pub fn build(particles: &ParticleStore) -> Self {
+        let mut tree = QuadTree {
+            nodes: Vec::with_capacity(particles.len() / 2 + 1),
+            order: (0..particles.len()).collect(),
+            children: 4,
+        };
+
+        if particles.is_empty() {
+            return tree;
+        }
+
+        // Root cell is the bounding square (or cube) of everything
+        let mut min_x = Real::MAX;
+        let mut min_y = Real::MAX;
+        let mut min_z = Real::MAX;
+        let mut max_x = Real::MIN;
+        let mut max_y = Real::MIN;
+        let mut max_z = Real::MIN;
+        for i in 0..particles.len() {
+            min_x = min_x.min(particles.x[i]);
+            min_y = min_y.min(particles.y[i]);
+            min_z = min_z.min(particles.z[i]);
+            max_x = max_x.max(particles.x[i]);
+            max_y = max_y.max(particles.y[i]);
+            max_z = max_z.max(particles.z[i]);
+        }
+        if max_z > min_z {
+            tree.children = 8;
+        }
+        let half_size = ((max_x - min_x).max(max_y - min_y).max(max_z - min_z) / 2.0).max(1.0) * 1.001;
+
+        tree.nodes.push(Node {
+            center_x: (min_x + max_x) / 2.0,
+            center_y: (min_y + max_y) / 2.0,
+            center_z: (min_z + max_z) / 2.0,
+            half_size,
+            mass: 0.0,
+            com_x: 0.0,
+            com_y: 0.0,
+            com_z: 0.0,
+            vel_x: 0.0,
+            vel_y: 0.0,
+            vel_z: 0.0,
+            first_child: 0,
+            start: 0,
+            count: particles.len(),
+        });
+        tree.subdivide(0, particles, 0);
+        tree
+    }
+
+    fn subdivide(&mut self, node_idx: usize, particles: &ParticleStore, depth: usize) {
+        let (center_x, center_y, center_z, half_size, start, count) = {
+            let node = &self.nodes[node_idx];
+            (node.center_x, node.center_y, node.center_z, node.half_size, node.start, node.count)
+        };
+
+        if count <= LEAF_CAPACITY || depth >= MAX_DEPTH {
+            // Leaf - add up the bodies directly
+            let mut mass = 0.0;
+            let mut weighted = (0.0, 0.0, 0.0);
+            let mut momentum = (0.0, 0.0, 0.0);
+            for &i in &self.order[start..start + count] {
+                let m = particles.mass[i];
+                mass += m;
+                weighted = (weighted.0 + m * particles.x[i], weighted.1 + m * particles.y[i], weighted.2 + m * particles.z[i]);
+                momentum = (momentum.0 + m * particles.vx[i], momentum.1 + m * particles.vy[i], momentum.2 + m * particles.vz[i]);
+            }
+            self.set_mass(node_idx, mass, weighted, momentum);
+            return;
+        }
+
+        // Sort the bodies of this node into quadrants: 0 = -x-y, 1 = +x-y, 2 = -x+y, 3 = +x+y,
+        // and in 3D the same again + 4 for the octants above the middle
+        let split_z = self.children == 8;
+        let quadrant = |i: usize| -> usize {
+            let east = (particles.x[i] >= center_x) as usize;
+            let north = (particles.y[i] >= center_y) as usize;
+            let up = (split_z && particles.z[i] >= center_z) as usize;
+            east + 2 * north + 4 * up
+        };
+        let slice = &mut self.order[start..start + count];
+        slice.sort_unstable_by_key(|&i| quadrant(i));
+
+        let mut counts = [0usize; 8];
+        for &i in slice.iter() {
+            counts[quadrant(i)] += 1;
+        }
+
+        let first_child = self.nodes.len();
+        let quarter = half_size / 2.0;
+        let mut child_start = start;
+        for (q, &child_count) in counts[..self.children].iter().enumerate() {
+            let offset_x = if q % 2 == 1 { quarter } else { -quarter };
+            let offset_y = if q % 4 >= 2 { quarter } else { -quarter };
+            let offset_z = if !split_z { 0.0 } else if q >= 4 { quarter } else { -quarter };
+            self.nodes.push(Node {
+                center_x: center_x + offset_x,
+                center_y: center_y + offset_y,
+                center_z: center_z + offset_z,
+                half_size: quarter,
+                mass: 0.0,
+                com_x: 0.0,
+                com_y: 0.0,
+                com_z: 0.0,
+                vel_x: 0.0,
+                vel_y: 0.0,
+                vel_z: 0.0,
+                first_child: 0,
+                start: child_start,
+                count: child_count,
+            });
+            child_start += child_count;
+        }
+        self.nodes[node_idx].first_child = first_child;
+
+        let mut mass = 0.0;
+        let mut weighted = (0.0, 0.0, 0.0);
+        let mut momentum = (0.0, 0.0, 0.0);
+        for child in first_child..first_child + self.children {
+            if self.nodes[child].count > 0 {
+                self.subdivide(child, particles, depth + 1);
+            }
+            let node = &self.nodes[child];
+            mass += node.mass;
+            weighted = (weighted.0 + node.mass * node.com_x, weighted.1 + node.mass * node.com_y,
+                        weighted.2 + node.mass * node.com_z);
+            momentum = (momentum.0 + node.mass * node.vel_x, momentum.1 + node.mass * node.vel_y,
+                        momentum.2 + node.mass * node.vel_z);
+        }
+        self.set_mass(node_idx, mass, weighted, momentum);
+    }
+
+    // `weighted` is the sum of mass times position over the bodies below the node,
+    // `momentum` the sum of mass times velocity
+    fn set_mass(&mut self, node_idx: usize, mass: Real, weighted: (Real, Real, Real), momentum: (Real, Real, Real)) {
+        let node = &mut self.nodes[node_idx];
+        node.mass = mass;
+        if mass > 0.0 {
+            node.com_x = weighted.0 / mass;
+            node.com_y = weighted.1 / mass;
+            node.com_z = weighted.2 / mass;
+            node.vel_x = momentum.0 / mass;
+            node.vel_y = momentum.1 / mass;
+            node.vel_z = momentum.2 / mass;
+        } else {
+            node.com_x = node.center_x;
+            node.com_y = node.center_y;
+            node.com_z = node.center_z;
+        }
+    }
+
+    // Walk the tree for one body. A node is opened when (node width / distance) >= theta,
+    // otherwise its whole mass pulls from the centre of mass
+    pub fn acceleration_on(&self, index: usize, particles: &ParticleStore,
+                           engine: &GravityEngine) -> (Real, Real, Real) {
+        let (x, y, z) = (particles.x[index], particles.y[index], particles.z[index]);
+        let body = &particles.objects[index];
+        // A cell has no size of its own to soften with, so it borrows the body's
+        let cell_softening = 2.0 * engine.softening_of(body);
+        let mut acceleration = (0.0, 0.0, 0.0);
+        self.walk(index, particles, engine.theta, |source| {
+            let (mass, dx, dy, dz, factor) = match source {
+                // Same pairwise rule as the direct sum
+                Source::Body(j) => {
+                    let (dx, dy, dz) = (particles.x[j] - x, particles.y[j] - y, particles.z[j] - z);
+                    (particles.mass[j], dx, dy, dz, engine.pair_factor(dx, dy, dz, body, &particles.objects[j]))
+                }
+                Source::Cell(node) => {
+                    let (dx, dy, dz) = (node.com_x - x, node.com_y - y, node.com_z - z);
+                    let factor = G * engine.softening_kernel.factor(dx * dx + dy * dy + dz * dz, cell_softening);
+                    (node.mass, dx, dy, dz, factor)
+                }
+            };
+            acceleration.0 += factor * mass * dx;
+            acceleration.1 += factor * mass * dy;
+            acceleration.2 += factor * mass * dz;
+        });
+        acceleration
+    }
+
+    // Potential per unit mass at the body from the same walk, each far cell as a point mass
+    // at its centre of mass. In f64 for the energy bookkeeping
+    pub fn potential_on(&self, index: usize, particles: &ParticleStore, engine: &GravityEngine) -> f64 {
+        let (x, y, z) = (to_f64(particles.x[index]), to_f64(particles.y[index]), to_f64(particles.z[index]));
+        let softening = engine.softening_of(&particles.objects[index]);
+        let cell_softening = 2.0 * softening;
+        let mut potential = 0.0;
+        self.walk(index, particles, engine.theta, |source| {
+            let (mass, position, length) = match source {
+                Source::Body(j) => (particles.mass[j], (particles.x[j], particles.y[j], particles.z[j]),
+                                    softening + engine.softening_of(&particles.objects[j])),
+                Source::Cell(node) => (node.mass, (node.com_x, node.com_y, node.com_z), cell_softening),
+            };
+            let (dx, dy, dz) = (to_f64(position.0) - x, to_f64(position.1) - y, to_f64(position.2) - z);
+            let r = (dx * dx + dy * dy + dz * dz).sqrt();
+            potential += to_f64(G) * to_f64(mass) * engine.softening_kernel.potential(r, to_f64(length));
+        });
+        potential
+    }
+
+    // Time derivative of acceleration_on, from the same walk: G m (factor dv + slope (d.dv) d)
+    // for each body, and for each far cell with the velocity of its centre of mass. O(n log n),
+    // where the direct-sum jerk is O(n²)
+    pub fn jerk_on(&self, index: usize, particles: &ParticleStore,
+                   engine: &GravityEngine) -> (Real, Real, Real) {
+        let body = particles.particle(index);
+        let softening = engine.softening_of(&particles.objects[index]);
+        let cell_softening = 2.0 * softening;
+        let mut jerk = (0.0, 0.0, 0.0);
+        self.walk(index, particles, engine.theta, |source| {
+            let (mass, position, velocity, length) = match source {
+                Source::Body(j) => (particles.mass[j], (particles.x[j], particles.y[j], particles.z[j]),
+                                    (particles.vx[j], particles.vy[j], particles.vz[j]),
+                                    softening + engine.softening_of(&particles.objects[j])),
+                Source::Cell(node) => (node.mass, (node.com_x, node.com_y, node.com_z),
+                                       (node.vel_x, node.vel_y, node.vel_z), cell_softening),
+            };
+            let (dx, dy, dz) = (position.0 - body.x, position.1 - body.y, position.2 - body.z);
+            let (dvx, dvy, dvz) = (velocity.0 - body.vx, velocity.1 - body.vy, velocity.2 - body.vz);
+            let (factor, slope) = engine.softening_kernel.factor_and_slope(dx * dx + dy * dy + dz * dz, length);
+            let radial = slope * (dx * dvx + dy * dvy + dz * dvz);
+            jerk.0 += G * mass * (factor * dvx + radial * dx);
+            jerk.1 += G * mass * (factor * dvy + radial * dy);
+            jerk.2 += G * mass * (factor * dvz + radial * dz);
+        });
+        jerk
+    }
+
+    // The walk both of those share: `visit` gets every other body in the leaves that get
+    // opened, and every cell that's far enough away to count as one body
+    fn walk<'a>(&'a self, index: usize, particles: &ParticleStore, theta: Real,
+                mut visit: impl FnMut(Source<'a>)) {
+        if self.nodes.is_empty() {
+            return;
+        }
+
+        let (x, y, z) = (particles.x[index], particles.y[index], particles.z[index]);
+        let theta_sq = theta * theta;
+
+        let mut stack = vec![0usize];
+        while let Some(node_idx) = stack.pop() {
+            let node = &self.nodes[node_idx];
+            if node.count == 0 {
+                continue;
+            }
+
+            if node.first_child == 0 {
+                for &j in &self.order[node.start..node.start + node.count] {
+                    if j != index {
+                        visit(Source::Body(j));
+                    }
+                }
+                continue;
+            }
+
+            let dx = node.com_x - x;
+            let dy = node.com_y - y;
+            let dz = node.com_z - z;
+            let distance_sq = dx * dx + dy * dy + dz * dz;
+            let width = node.half_size * 2.0;
+
+            if width * width < theta_sq * distance_sq && !node.contains(x, y, z) {
+                // Far enough away - treat the whole cell as one body
+                visit(Source::Cell(node));
+            } else {
+                stack.extend(node.first_child..node.first_child + self.children);
+            }
+        }
+    }
+}
+
+// What pulls on a body during the walk: another body (by its index), or a whole cell
+enum Source<'a> {
+    Body(usize),
+    Cell(&'a Node),
+}
+
+impl Node {
+    fn contains(&self, x: Real, y: Real, z: Real) -> bool {
+        (x - self.center_x).abs() <= self.half_size
+            && (y - self.center_y).abs() <= self.half_size
+            && (z - self.center_z).abs() <= self.half_size
+    }
+}
@@ -0,0 +1,491 @@
+use rand::Rng;
+use crate::force_field::ForceField;
+use crate::gravity::G;
+use crate::objects::{CelestialObject, ObjectType};
+use crate::particles::{Body, Particle, ParticleStore};
+use crate::real::{consts, to_f64, Real};
+
+// What starlight does to small bodies
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RadiationMode {
+    Off,
+    Pressure,          // starlight pushes small bodies away from the star
+    PoyntingRobertson, // ...and the drag from absorbing it head-on makes dust spiral in
+}
+
+impl RadiationMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RadiationMode::Off => "Off",
+            RadiationMode::Pressure => "Pressure",
+            RadiationMode::PoyntingRobertson => "Pressure + PR Drag",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            RadiationMode::Off => RadiationMode::Pressure,
+            RadiationMode::Pressure => RadiationMode::PoyntingRobertson,
+            RadiationMode::PoyntingRobertson => RadiationMode::Off,
+        }
+    }
+}
+
+// Stars and white dwarfs shine on everything else
+fn is_light_source(obj: &CelestialObject) -> bool {
+    obj.luminosity > 0.0 && matches!(obj.object_type, ObjectType::Star | ObjectType::WhiteDwarf)
+}
+
+// Only small bodies have enough surface for their mass to notice
+fn feels_radiation(obj: &CelestialObject) -> bool {
+    matches!(obj.object_type, ObjectType::Comet | ObjectType::Asteroid | ObjectType::Dust)
+}
+
+// The index of the light source closest to body i, and how far away it is
+fn nearest_light_source(particles: &ParticleStore, i: usize) -> Option<(usize, Real)> {
+    (0..particles.len())
+        .filter(|&j| j != i && is_light_source(&particles.objects[j]))
+        .map(|j| {
+            let dx = particles.x[i] - particles.x[j];
+            let dy = particles.y[i] - particles.y[j];
+            let dz = particles.z[i] - particles.z[j];
+            (j, (dx * dx + dy * dy + dz * dz).sqrt())
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+}
+
+// Starlight on the small bodies, as a force field. A body of cross-section A = π R² and
+// mass m at distance r from a star of luminosity L absorbs the flux S = L / (4π r²), and
+// with it momentum at the rate S A / c. Per unit mass that's
+//   a = Q S A / (m c) r̂,
+// which falls off as 1/r² just like gravity, so all that matters for a grain is
+// β = a_radiation / a_gravity = Q L A / (4π c G M m): the star pulls on it as if it had
+// (1 - β) of its mass, and grains with β > 1/2 released from a circular orbit escape.
+// With Poynting-Robertson drag the light is also seen aberrated and Doppler shifted
+// by the grain's motion relative to the star (Burns, Lamy & Soter 1979):
+//   a = Q S A / (m c) [(1 - ṙ / c) r̂ - v / c],
+// and the -v/c term takes away angular momentum, so dust spirals in. That's only the first
+// order in v/c, so the velocity it sees is capped at MAX_DRAG_SPEED c. Both the push and
+// the drag are linear in the flux, so light from several stars adds up star by star.
+// Nothing pushes back on the star (the light carries the momentum away), and the drag
+// isn't conservative, so both show up in the conservation diagnostics. The push alone
+// has a potential, which the diagnostics do count. The stars are found once per step
+// (in `prepare`), so each small body only loops over those
+pub struct RadiationPressure {
+    pub speed_of_light: Real,
+    pub efficiency: Real, // Q: 1 for a grain that absorbs everything, up to 2 for a mirror
+    pub drag: bool,       // Poynting-Robertson drag on top of the pressure
+    sources: Vec<usize>, // indices of the light sources at the last `prepare`
+}
+
+// Fastest motion relative to a star, as a fraction of c, the drag's expansion is trusted at
+const MAX_DRAG_SPEED: Real = 0.1;
+
+impl RadiationPressure {
+    pub const NAME: &'static str = "Radiation";
+
+    pub fn new(speed_of_light: Real, efficiency: Real, drag: bool) -> Self {
+        RadiationPressure {
+            speed_of_light,
+            efficiency,
+            drag,
+            sources: Vec::new(),
+        }
+    }
+
+    // Ratio of the light's push to the star's pull on `body` (both indices into the store)
+    pub fn beta(&self, particles: &ParticleStore, body: usize, source: usize) -> Real {
+        let radius = particles.objects[body].radius;
+        self.efficiency * particles.objects[source].luminosity * radius * radius
+            / (4.0 * self.speed_of_light * G * particles.mass[source] * particles.mass[body])
+    }
+
+    // A dust grain that feels `beta` of the pull of a star with luminosity-to-mass ratio
+    // `light_to_mass`. All grains are drawn the same size; β sets their mass
+    pub fn dust_grain(&self, position: (Real, Real, Real), velocity: (Real, Real, Real), beta: Real,
+                      light_to_mass: Real, age: f64) -> Body {
+        let radius = 0.05;
+        let mass = self.efficiency * light_to_mass * radius * radius / (4.0 * self.speed_of_light * G * beta);
+        let grain = Particle {
+            x: position.0,
+            y: position.1,
+            z: position.2,
+            vx: velocity.0,
+            vy: velocity.1,
+            vz: velocity.2,
+            mass,
+            ..Particle::default()
+        };
+        (grain, CelestialObject::new(radius, mass, ObjectType::Dust, age))
+    }
+}
+
+impl ForceField for RadiationPressure {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn prepare(&mut self, particles: &ParticleStore) {
+        self.sources.clear();
+        self.sources.extend((0..particles.len()).filter(|&j| is_light_source(&particles.objects[j])));
+    }
+
+    fn acceleration_on(&self, i: usize, particles: &ParticleStore) -> (Real, Real, Real) {
+        let radius = particles.objects[i].radius;
+        if !feels_radiation(&particles.objects[i]) {
+            return (0.0, 0.0, 0.0);
+        }
+        let body = particles.particle(i);
+        let area_to_mass = consts::PI * radius * radius / body.mass;
+        let c = self.speed_of_light;
+
+        let mut acceleration = (0.0, 0.0, 0.0);
+        for &j in &self.sources {
+            let (source, source_object) = (particles.particle(j), &particles.objects[j]);
+            let (dx, dy, dz) = (body.x - source.x, body.y - source.y, body.z - source.z);
+            // Inside the star's radius the push fades linearly to nothing at the centre,
+            // like gravity inside a uniform ball
+            let r_sq = (dx * dx + dy * dy + dz * dz).max(source_object.radius * source_object.radius);
+            let r = r_sq.sqrt();
+            let (nx, ny, nz) = (dx / r, dy / r, dz / r);
+            let push = self.efficiency * source_object.luminosity * area_to_mass / (4.0 * consts::PI * r_sq * c);
+
+            if self.drag {
+                let (mut vx, mut vy, mut vz) = (body.vx - source.vx, body.vy - source.vy, body.vz - source.vz);
+                // Past v ~ c the first-order terms would turn the drag into a push
+                let speed = (vx * vx + vy * vy + vz * vz).sqrt();
+                if speed > MAX_DRAG_SPEED * c {
+                    let scale = MAX_DRAG_SPEED * c / speed;
+                    (vx, vy, vz) = (vx * scale, vy * scale, vz * scale);
+                }
+                let radial = 1.0 - (nx * vx + ny * vy + nz * vz) / c;
+                acceleration.0 += push * (radial * nx - vx / c);
+                acceleration.1 += push * (radial * ny - vy / c);
+                acceleration.2 += push * (radial * nz - vz / c);
+            } else {
+                acceleration.0 += push * nx;
+                acceleration.1 += push * ny;
+                acceleration.2 += push * nz;
+            }
+        }
+        acceleration
+    }
+
+    // The push is K r̂ / r² with K = Q L A / (4π m c), the pull of a negative mass, so its
+    // potential is K / r. Inside the star's radius R, where the push fades to nothing
+    // at the centre, it's K (3R² - r²) / 2R³. The drag has none
+    fn potential_of(&self, i: usize, particles: &ParticleStore) -> f64 {
+        let radius = particles.objects[i].radius;
+        if !feels_radiation(&particles.objects[i]) {
+            return 0.0;
+        }
+        let body = particles.particle(i);
+        let area_to_mass = to_f64(consts::PI * radius * radius / body.mass);
+        let c = to_f64(self.speed_of_light);
+
+        let mut potential = 0.0;
+        for &j in &self.sources {
+            let (source, source_object) = (particles.particle(j), &particles.objects[j]);
+            let strength = to_f64(self.efficiency * source_object.luminosity) * area_to_mass / (4.0 * std::f64::consts::PI * c);
+            let (dx, dy, dz) = (to_f64(body.x - source.x), to_f64(body.y - source.y), to_f64(body.z - source.z));
+            let r = (dx * dx + dy * dy + dz * dz).sqrt();
+            let floor = to_f64(source_object.radius);
+            potential += if r >= floor {
+                strength / r
+            } else {
+                strength * (3.0 * floor * floor - r * r) / (2.0 * floor * floor * floor)
+            };
+        }
+        potential
+    }
+}
+
+// Radiation settings, and comets shedding dust. A comet near a star sheds grains from its
+// night side, which the light then blows back into a tail. Light travels at the gravity
+// engine's turned-down c, so the caller passes that in
+pub struct RadiationEngine {
+    pub mode: RadiationMode, // Off by default, so scenes play out as they always have
+    pub efficiency: Real,
+    pub comet_dust: bool,        // comets shed dust while the light is on (off by default)
+    pub activity_distance: Real, // comets shed dust inside this distance from a star...
+    pub shedding_interval: Real, // ...about once per this much time at that distance, more often closer in
+    pub max_grains: usize,       // no more shedding while this many dust grains are around
+    pub grains_shed: u32,        // grains comets have shed so far
+}
+
+impl RadiationEngine {
+    pub fn new() -> Self {
+        RadiationEngine {
+            mode: RadiationMode::Off,
+            efficiency: 1.0,
+            comet_dust: false,
+            activity_distance: 150.0,
+            shedding_interval: 2.0,
+            max_grains: 300,
+            grains_shed: 0,
+        }
+    }
+
+    // The force field for the current mode, if the light does anything at all
+    pub fn field(&self, speed_of_light: Real) -> Option<RadiationPressure> {
+        if self.mode == RadiationMode::Off {
+            return None;
+        }
+        Some(RadiationPressure::new(speed_of_light, self.efficiency, self.mode == RadiationMode::PoyntingRobertson))
+    }
+
+    // β of a small body towards the nearest star, for the info panel. None if the light
+    // is off, the body is too big to care, or there's no star
+    pub fn beta_of(&self, particles: &ParticleStore, index: usize, speed_of_light: Real) -> Option<Real> {
+        let field = self.field(speed_of_light)?;
+        if !feels_radiation(&particles.objects[index]) {
+            return None;
+        }
+        let (star, _) = nearest_light_source(particles, index)?;
+        Some(field.beta(particles, index, star))
+    }
+
+    // Let comets near stars shed dust over the last step of length dt. Each grain leaves
+    // from the comet's night side with a little push away from the star, and its mass and
+    // momentum come out of the comet. Returns true if any grain was shed
+    pub fn shed_comet_dust(&mut self, particles: &mut ParticleStore, speed_of_light: Real,
+                           dt: Real, age: f64) -> bool {
+        if !self.comet_dust {
+            return false;
+        }
+        let Some(light) = self.field(speed_of_light) else {
+            return false;
+        };
+        let mut grains = particles.objects.iter().filter(|obj| obj.object_type == ObjectType::Dust).count();
+        if grains >= self.max_grains {
+            return false;
+        }
+
+        let mut rng = rand::thread_rng();
+        let mut shed = Vec::new();
+        for i in 0..particles.len() {
+            if particles.objects[i].object_type != ObjectType::Comet || grains >= self.max_grains {
+                continue;
+            }
+            // The nearest star does the heating
+            let Some((s, distance)) = nearest_light_source(particles, i) else {
+                continue;
+            };
+            let (mut comet, star) = (particles.particle(i), particles.particle(s));
+            let comet_radius = particles.objects[i].radius;
+            if distance >= self.activity_distance || distance <= 0.0 {
+                continue;
+            }
+            // Heating goes as the flux, 1/r²
+            let closeness = self.activity_distance / distance;
+            let chance = (dt / self.shedding_interval * closeness * closeness).min(1.0);
+            if !rng.gen_bool(to_f64(chance)) {
+                continue;
+            }
+
+            let (nx, ny, nz) = ((comet.x - star.x) / distance, (comet.y - star.y) / distance, (comet.z - star.z) / distance);
+            let ejection = 0.05;
+            let (grain, dust) = light.dust_grain(
+                (comet.x + nx * comet_radius, comet.y + ny * comet_radius, comet.z + nz * comet_radius),
+                (comet.vx + nx * ejection, comet.vy + ny * ejection, comet.vz + nz * ejection),
+                rng.gen_range(0.05..1.5),
+                particles.objects[s].luminosity / star.mass,
+                age,
+            );
+            if grain.mass >= comet.mass * 0.01 {
+                continue;
+            }
+
+            // The comet recoils, and steps back so the pair's centre of mass stays put
+            let left = comet.mass - grain.mass;
+            let share = grain.mass / left;
+            comet.x -= nx * comet_radius * share;
+            comet.y -= ny * comet_radius * share;
+            comet.z -= nz * comet_radius * share;
+            comet.vx -= nx * ejection * share;
+            comet.vy -= ny * ejection * share;
+            comet.vz -= nz * ejection * share;
+            comet.mass = left;
+            particles.set_particle(i, comet);
+            shed.push((grain, dust));
+            grains += 1;
+        }
+
+        self.grains_shed += shed.len() as u32;
+        let any = !shed.is_empty();
+        particles.extend(shed);
+        any
+    }
+}
+
+impl Default for RadiationEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::PhysicsEngine;
+    use crate::test_support::{assert_kept, totals};
+
+    const STAR_MASS: Real = 1e5;
+    const STAR_RADIUS: Real = 5.0;
+    const C: Real = 300.0;
+
+    // A star at rest at the origin and a grain built for `beta` at `position`, moving
+    // with `velocity`
+    fn star_and_grain(light: &RadiationPressure, beta: Real, position: (Real, Real, Real),
+                      velocity: (Real, Real, Real)) -> ParticleStore {
+        let star = CelestialObject::new(STAR_RADIUS, STAR_MASS, ObjectType::Star, 0.0);
+        let grain = light.dust_grain(position, velocity, beta, star.luminosity / STAR_MASS, 0.0);
+        [(Particle::new(0.0, 0.0, 0.0, 0.0, STAR_MASS), star), grain].into_iter().collect()
+    }
+
+    fn pull_on_grain(light: &mut RadiationPressure, particles: &ParticleStore) -> (f64, f64, f64) {
+        light.prepare(particles);
+        let (ax, ay, az) = light.acceleration_on(1, particles);
+        (to_f64(ax), to_f64(ay), to_f64(az))
+    }
+
+    // A grain made for β reports β back
+    #[test]
+    fn dust_grain_has_the_beta_it_was_built_for() {
+        let light = RadiationPressure::new(C, 1.3, false);
+        for beta in [0.05, 0.5, 1.2] {
+            let particles = star_and_grain(&light, beta, (100.0, 0.0, 0.0), (0.0, 0.0, 0.0));
+            let measured = light.beta(&particles, 1, 0);
+            assert!((to_f64(measured / beta) - 1.0).abs() < 1e-5, "built for {beta}, has {measured}");
+        }
+    }
+
+    // Outside the star the push is β times the star's pull, straight away from it, and falls
+    // off as 1/r². Inside it fades linearly to nothing at the centre
+    #[test]
+    fn push_is_beta_times_the_pull() {
+        let mut light = RadiationPressure::new(C, 1.0, false);
+        let beta = 0.4;
+        let gm = to_f64(G * STAR_MASS);
+        let direction = (0.6, -0.48, 0.64);
+        for r in [1.0, 2.5, 4.0, 20.0, 100.0, 400.0] {
+            let at = (direction.0 * r, direction.1 * r, direction.2 * r);
+            let particles = star_and_grain(&light, beta, at, (0.0, 0.0, 0.0));
+            let (ax, ay, az) = pull_on_grain(&mut light, &particles);
+            let (r, radius) = (to_f64(r), to_f64(STAR_RADIUS));
+            let push = to_f64(beta) * gm * if r >= radius { 1.0 / (r * r) } else { r / (radius * radius * radius) };
+            let expected = (push * to_f64(direction.0), push * to_f64(direction.1), push * to_f64(direction.2));
+            for (a, e) in [(ax, expected.0), (ay, expected.1), (az, expected.2)] {
+                assert!((a - e).abs() < 1e-5 * push, "r = {r}: ({ax:e}, {ay:e}, {az:e}) vs {expected:?}");
+            }
+        }
+    }
+
+    // The push is minus the gradient of potential_of, by central differences, inside the
+    // star and out
+    #[test]
+    fn push_is_minus_the_gradient_of_the_potential() {
+        let mut light = RadiationPressure::new(C, 1.0, false);
+        for (x, y, z) in [(1.5, -2.0, 1.0), (3.0, 2.5, -1.5), (60.0, 25.0, -40.0), (-300.0, 120.0, 50.0)] {
+            let mut particles = star_and_grain(&light, 0.7, (x, y, z), (0.0, 0.0, 0.0));
+            let (ax, ay, az) = pull_on_grain(&mut light, &particles);
+            let h = 1e-3 * (x * x + y * y + z * z).sqrt();
+            let mut potential_at = |dx: Real, dy: Real, dz: Real| {
+                (particles.x[1], particles.y[1], particles.z[1]) = (x + dx, y + dy, z + dz);
+                light.potential_of(1, &particles)
+            };
+            let slope = |plus: f64, minus: f64| -(plus - minus) / (2.0 * to_f64(h));
+            let numeric = [
+                slope(potential_at(h, 0.0, 0.0), potential_at(-h, 0.0, 0.0)),
+                slope(potential_at(0.0, h, 0.0), potential_at(0.0, -h, 0.0)),
+                slope(potential_at(0.0, 0.0, h), potential_at(0.0, 0.0, -h)),
+            ];
+            let size = (ax * ax + ay * ay + az * az).sqrt();
+            for (axis, (a, n)) in [ax, ay, az].into_iter().zip(numeric).enumerate() {
+                assert!((a - n).abs() < 1e-3 * size, "at ({x}, {y}, {z}), axis {axis}: {a:e} vs {n:e}");
+            }
+        }
+    }
+
+    // On a circular orbit Poynting-Robertson drag works against the motion: the grain
+    // feels a torque -push v r / c about the star, and over a few steps it ends up with
+    // less angular momentum than the same grain under the push alone
+    #[test]
+    fn poynting_robertson_drag_takes_angular_momentum_from_a_circular_orbit() {
+        let (beta, r) = (0.3, 100.0);
+        let gm = to_f64(G * STAR_MASS);
+        let speed = (gm * (1.0 - to_f64(beta)) / to_f64(r)).sqrt();
+        let mut drag = RadiationPressure::new(C, 1.0, true);
+        let particles = star_and_grain(&drag, beta, (r, 0.0, 0.0), (0.0, speed as Real, 0.0));
+
+        // r × a, with the grain on the x axis
+        let torque = to_f64(r) * pull_on_grain(&mut drag, &particles).1;
+        let push = to_f64(beta) * gm / (to_f64(r) * to_f64(r));
+        let expected = -push * speed * to_f64(r) / to_f64(C);
+        assert!((torque - expected).abs() < 1e-3 * expected.abs(), "torque {torque:e}, expected {expected:e}");
+
+        // The grain's angular momentum per unit mass about the star after some steps
+        let orbit = |drag: bool| {
+            let mut engine = PhysicsEngine::new();
+            engine.gravity_engine.softening_length = 0.1;
+            engine.add_force_field(RadiationPressure::new(C, 1.0, drag));
+            let mut particles = particles.clone();
+            for _ in 0..1000 {
+                engine.update_physics(&mut particles, 1.0);
+            }
+            let (x, y) = (to_f64(particles.x[1] - particles.x[0]), to_f64(particles.y[1] - particles.y[0]));
+            let (vx, vy) = (to_f64(particles.vx[1] - particles.vx[0]), to_f64(particles.vy[1] - particles.vy[0]));
+            x * vy - y * vx
+        };
+        let (with_drag, without) = (orbit(true), orbit(false));
+        let elapsed = 1000.0 * to_f64(PhysicsEngine::new().time_step(1.0));
+        let lost = without - with_drag;
+        assert!((lost + expected * elapsed).abs() < 0.1 * (expected * elapsed).abs(),
+                "lost {lost:e} of {without:e}, expected {:e}", -expected * elapsed);
+    }
+
+    // Comets close to a star with nothing else around, sure to shed this step
+    fn comets_near_a_star(count: usize) -> (RadiationEngine, ParticleStore) {
+        let mut engine = RadiationEngine::new();
+        engine.mode = RadiationMode::Pressure;
+        engine.comet_dust = true;
+        engine.shedding_interval = 1e-6;
+        let mut particles: ParticleStore = [(Particle::new(0.0, 0.0, 0.0, 0.0, STAR_MASS),
+                                             CelestialObject::new(STAR_RADIUS, STAR_MASS, ObjectType::Star, 0.0))]
+            .into_iter().collect();
+        for k in 0..count {
+            let angle = k as Real * 1.3;
+            let (x, y) = (40.0 * angle.cos(), 40.0 * angle.sin());
+            particles.push(Particle::new(x, y, -0.3 * y, 0.3 * x, 1.0), CelestialObject::new(0.5, 1.0, ObjectType::Comet, 0.0));
+            particles.z[k + 1] = 2.0 * k as Real;
+            particles.vz[k + 1] = -0.1;
+        }
+        (engine, particles)
+    }
+
+    // Each grain's mass, momentum and place come out of its comet
+    #[test]
+    fn shedding_keeps_mass_momentum_and_centre_of_mass() {
+        let (mut engine, mut particles) = comets_near_a_star(5);
+        let before = totals(&particles);
+
+        assert!(engine.shed_comet_dust(&mut particles, C, 0.1, 0.0));
+        assert_eq!(engine.grains_shed, 5);
+        assert_eq!(particles.len(), 1 + 5 + 5);
+        assert!(particles.objects[6..].iter().all(|obj| obj.object_type == ObjectType::Dust));
+        assert_kept(before, totals(&particles));
+    }
+
+    // No more grains than max_grains, counting the ones already around
+    #[test]
+    fn shedding_stops_at_max_grains() {
+        let (mut engine, mut particles) = comets_near_a_star(5);
+        engine.max_grains = 3;
+        let dust = |particles: &ParticleStore| particles.objects.iter().filter(|obj| obj.object_type == ObjectType::Dust).count();
+
+        assert!(engine.shed_comet_dust(&mut particles, C, 0.1, 0.0));
+        assert_eq!((dust(&particles), engine.grains_shed), (3, 3));
+        assert!(!engine.shed_comet_dust(&mut particles, C, 0.1, 0.0));
+        assert_eq!((dust(&particles), engine.grains_shed), (3, 3));
+    }
+}
@@ -0,0 +1,34 @@
+// The float type the physics runs in. f32 by default - fast, and SIMD gets twice the
+// lanes - or f64 with the `f64` feature, for long integrations and bodies far from the
+// origin, where f32 positions only resolve about 1e-5 of a unit per 100 units
+#[cfg(not(feature = "f64"))]
+pub type Real = f32;
+#[cfg(feature = "f64")]
+pub type Real = f64;
+
+#[cfg(not(feature = "f64"))]
+pub use std::f32::consts;
+#[cfg(feature = "f64")]
+pub use std::f64::consts;
+
+// Down to f32 for drawing and the UI
+#[cfg(not(feature = "f64"))]
+pub fn to_f32(value: Real) -> f32 {
+    value
+}
+
+#[cfg(feature = "f64")]
+pub fn to_f32(value: Real) -> f32 {
+    value as f32
+}
+
+// Up to f64 for the bookkeeping that sums in double precision either way
+#[cfg(not(feature = "f64"))]
+pub fn to_f64(value: Real) -> f64 {
+    f64::from(value)
+}
+
+#[cfg(feature = "f64")]
+pub fn to_f64(value: Real) -> f64 {
+    value
+}
@@ -1,9 +1,26 @@
 use macroquad::prelude::*;
-use crate::universe::Universe;
-use crate::objects::{ObjectType, CelestialObject};
+use bigbang_simulator::universe::Universe;
+use bigbang_simulator::objects::{ObjectType, CelestialObject};
+use bigbang_simulator::particles::{Particle, ParticleStore};
+use crate::camera::{Camera, Projected};
+use bigbang_simulator::physics::VelocityPolicy;
+use bigbang_simulator::units;
+use bigbang_simulator::real::{consts, to_f32, to_f64, Real};
 
 pub struct Renderer;
 
+// What the help panel shows besides the universe itself: the window and the controls
+// main.rs keeps track of
+#[derive(Clone, Copy)]
+pub struct HudState<'a> {
+    pub expansion_speed: f32,
+    pub zoom: f32,
+    pub time_scale: &'a str,
+    pub fullscreen: bool,
+    pub screen_width: f32,
+    pub screen_height: f32,
+}
+
 impl Renderer {
     pub fn new() -> Self {
         Renderer
@@ -50,39 +67,214 @@ impl Renderer {
     }
     
     // Main drwing function - orchestrates the whole cosmic show
-    pub fn draw_universe(&self, universe: &Universe, zoom: f32, offset_x: f32, offset_y: f32, 
-                        screen_width: f32, screen_height: f32) {
-        let center_x = screen_width / 2.0 + offset_x;
-        let center_y = screen_height / 2.0 + offset_y;
+    pub fn draw_universe(&self, universe: &Universe, camera: &Camera, screen_width: f32, screen_height: f32) {
+        let zoom = camera.zoom;
+        let center_x = screen_width / 2.0 + camera.offset_x;
+        let center_y = screen_height / 2.0 + camera.offset_y;
         
         self.draw_subtle_background(center_x, center_y, zoom, screen_width, screen_height);
+        self.draw_halos(universe, camera);
         
         // Draw orbits when user zoomed in
         if zoom > 1.5 {
-            self.draw_stable_orbits(&universe.objects, center_x, center_y, zoom);
+            self.draw_stable_orbits(&universe.particles, camera);
         }
         
-        // Draw dark matter/energy first
-        for obj in &universe.objects {
-            if matches!(obj.object_type, 
-                ObjectType::DarkEnergy | 
-                ObjectType::DarkMatter) {
-                self.draw_stable_object(obj, center_x, center_y, zoom);
+        // Far things first, so nearer ones cover them (in a flat view everything is at depth 0)
+        let mut visible: Vec<(Particle, &CelestialObject, Projected)> = universe.particles.iter()
+            .filter_map(|(body, obj)| camera.project_object(&body).map(|point| (body, obj, point)))
+            .collect();
+        visible.sort_by(|a, b| a.2.depth.total_cmp(&b.2.depth));
+        
+        // Draw dark energy first
+        for (body, obj, point) in &visible {
+            if obj.object_type == ObjectType::DarkEnergy {
+                self.draw_stable_object(body, obj, *point);
             }
         }
         
         // Then draw the main attractions
-        for obj in &universe.objects {
-            if !matches!(obj.object_type, 
-                ObjectType::DarkEnergy | 
-                ObjectType::DarkMatter) {
-                self.draw_stable_object(obj, center_x, center_y, zoom);
+        for (body, obj, point) in &visible {
+            if obj.object_type != ObjectType::DarkEnergy {
+                self.draw_stable_object(body, obj, *point);
             }
         }
         
         // Draw connections between close objects when really zoomed in
         if zoom > 2.5 {
-            self.draw_object_connections(&universe.objects, center_x, center_y, zoom);
+            self.draw_object_connections(&universe.particles, camera);
+        }
+        
+        self.draw_event_flashes(universe, camera);
+    }
+    
+    // A fading ring where each recent event happened
+    fn draw_event_flashes(&self, universe: &Universe, camera: &Camera) {
+        let lifetime = 1.0; // universe age, about 100 frames at normal speed
+        for event in universe.events.since(universe.age, lifetime) {
+            let Some(point) = camera.project(event.x, event.y, event.z) else {
+                continue;
+            };
+            let progress = ((universe.age - event.age) / lifetime) as f32;
+            let radius = 4.0 + progress * 20.0;
+            draw_circle_lines(point.x, point.y, radius, 1.5, Color::new(1.0, 0.5, 0.9, 1.0 - progress));
+        }
+    }
+    
+    // Faint ring where each dark matter halo's density profile bends (its scale radius)
+    fn draw_halos(&self, universe: &Universe, camera: &Camera) {
+        for halo in &universe.physics_engine.gravity_engine.halos {
+            let ring = Ring { centre: halo.centre, radius: halo.scale_radius, normal: (0.0, 0.0, 1.0) };
+            self.draw_ring(camera, ring, 2.0, Color::new(0.3, 0.3, 0.6, 0.25));
+        }
+    }
+    
+    // Seen flat a ring is an ordinary circle; in 3D it's drawn as a polygon so it tilts
+    // with the view
+    fn draw_ring(&self, camera: &Camera, ring: Ring, thickness: f32, color: Color) {
+        if !camera.perspective {
+            if let Some(point) = camera.project(ring.centre.0, ring.centre.1, ring.centre.2) {
+                draw_circle_lines(point.x, point.y, to_f32(ring.radius) * point.scale, thickness, color);
+            }
+            return;
+        }
+        let points = ring_points(camera, ring, 48);
+        for k in 0..points.len() {
+            if let (Some(a), Some(b)) = (points[k], points[(k + 1) % points.len()]) {
+                draw_line(a.0, a.1, b.0, b.1, thickness, color);
+            }
+        }
+    }
+    
+    // Gravitational-wave numbers for the selected compact binary, under the info panel,
+    // with a sketch of the chirping waveform over the next few cycles
+    pub fn draw_binary_readout(&self, universe: &Universe, index: usize, screen_width: f32) {
+        if screen_width < 400.0 {
+            return;
+        }
+        let gravity = &universe.physics_engine.gravity_engine;
+        let Some(readout) = universe.gravitational_waves.binary_readout(&universe.particles, index, gravity) else {
+            return;
+        };
+        
+        let box_x = 20.0;
+        let box_y = 310.0;
+        let box_width = 350.0_f32.min(screen_width * 0.3);
+        let box_height = 200.0;
+        draw_rectangle(box_x, box_y, box_width, box_height, Color::new(0.0, 0.0, 0.0, 0.85));
+        draw_rectangle_lines(box_x, box_y, box_width, box_height, 1.5, Color::new(1.0, 0.6, 0.9, 0.8));
+        
+        let lines = [
+            "GRAVITATIONAL WAVES".to_string(),
+            format!("Partner: {}  Separation: {}",
+                universe.particles.objects[readout.partner].object_type.name(), units::format_length(readout.separation)),
+            format!("Chirp mass: {}", units::format_mass(readout.chirp_mass)),
+            format!("Frequency: {:.3e} Hz  Chirp: {:.2e} Hz/s",
+                to_f64(readout.frequency) / units::TIME_S, to_f64(readout.chirp) / (units::TIME_S * units::TIME_S)),
+            format!("Strain h: {:.2e} at {}", readout.strain,
+                units::format_length(universe.gravitational_waves.observer_distance)),
+            format!("Merger in: {}", units::format_time(to_f64(readout.time_to_merger))),
+        ];
+        for (i, line) in lines.iter().enumerate() {
+            let color = if i == 0 { Color::new(1.0, 0.7, 0.9, 1.0) } else { WHITE };
+            draw_text(line, box_x + 10.0, box_y + 22.0 + i as f32 * 20.0, 16.0, color);
+        }
+        
+        // h(t) = h cos(2 pi phase), the frequency creeping up at the chirp rate.
+        // Shows about six cycles at the current frequency
+        let graph_y = box_y + box_height - 30.0;
+        let graph_width = box_width - 20.0;
+        let (frequency, chirp) = (to_f32(readout.frequency), to_f32(readout.chirp));
+        let window = 6.0 / frequency.max(f32::EPSILON);
+        let samples = 120;
+        let mut previous: Option<(f32, f32)> = None;
+        for k in 0..=samples {
+            let t = window * k as f32 / samples as f32;
+            let f = frequency + chirp * t;
+            let phase = frequency * t + 0.5 * chirp * t * t;
+            let grow = (f / frequency).powf(2.0 / 3.0).min(3.0);
+            let h = (2.0 * std::f32::consts::PI * phase).cos() * grow;
+            let point = (box_x + 10.0 + graph_width * k as f32 / samples as f32, graph_y - h * 8.0);
+            if let Some(last) = previous {
+                draw_line(last.0, last.1, point.0, point.1, 1.0, Color::new(1.0, 0.6, 0.9, 0.9));
+            }
+            previous = Some(point);
+        }
+    }
+    
+    // Conserved totals and how far they've drifted since t = 0, in the bottom right corner.
+    // Drifts go from green to red as they pass 0.1% and 1%
+    pub fn draw_diagnostics(&self, universe: &Universe, screen_width: f32, screen_height: f32) {
+        let (Some(now), Some(drift)) = (universe.diagnostics.current, universe.diagnostics.drift()) else {
+            return;
+        };
+        
+        let box_width = (if universe.three_d { 420.0_f32 } else { 340.0 }).min(screen_width * 0.4);
+        let box_height = 211.0;
+        let box_x = screen_width - box_width - 20.0;
+        let box_y = screen_height - box_height - 20.0;
+        draw_rectangle(box_x, box_y, box_width, box_height, Color::new(0.0, 0.0, 0.0, 0.85));
+        draw_rectangle_lines(box_x, box_y, box_width, box_height, 1.5, Color::new(0.6, 0.9, 1.0, 0.8));
+        
+        let grade = |value: f64| if value < 1e-3 {
+            Color::new(0.6, 1.0, 0.6, 1.0)
+        } else if value < 1e-2 {
+            Color::new(1.0, 0.9, 0.5, 1.0)
+        } else {
+            Color::new(1.0, 0.5, 0.5, 1.0)
+        };
+        // (x, y) - or (x, y, z) in 3D - in SI, given the size of one simulation unit
+        let vector = |v: (f64, f64, f64), unit: f64, precision: usize| if universe.three_d {
+            format!("({:.*e}, {:.*e}, {:.*e})", precision, v.0 * unit, precision, v.1 * unit, precision, v.2 * unit)
+        } else {
+            format!("({:.*e}, {:.*e})", precision, v.0 * unit, precision, v.1 * unit)
+        };
+        let momentum_unit = units::MASS_KG * units::SPEED_M_PER_S;
+        // A flat scene only spins about z
+        let angular_momentum = if universe.three_d {
+            vector(now.angular_momentum, momentum_unit * units::LENGTH_M, 2)
+        } else {
+            format!("{:.3e}", now.angular_momentum.2 * momentum_unit * units::LENGTH_M)
+        };
+        let lines = [
+            ("CONSERVATION".to_string(), Color::new(0.6, 0.9, 1.0, 1.0)),
+            (format!("Energy: {:.4e} J (K {:.2e}, U {:.2e})", units::energy_to_joules(now.energy()),
+                units::energy_to_joules(now.kinetic), units::energy_to_joules(now.potential)), WHITE),
+            (format!("  drift {:.2e}  worst {:.2e}", drift.energy, universe.diagnostics.worst_energy_drift),
+                grade(drift.energy)),
+            (format!("Momentum: {} kg m/s  drift {:.2e}", vector(now.momentum, momentum_unit, 2), drift.momentum),
+                grade(drift.momentum)),
+            (format!("Angular momentum: {} kg m²/s  drift {:.2e}", angular_momentum, drift.angular_momentum),
+                grade(drift.angular_momentum)),
+            (format!("Centre of mass: ({:.1}, {:.1}{}) AU  drift {:.2e}",
+                now.centre_of_mass.0, now.centre_of_mass.1,
+                if universe.three_d { format!(", {:.1}", now.centre_of_mass.2) } else { String::new() },
+                drift.centre_of_mass), grade(drift.centre_of_mass)),
+            (format!("Mass: {:.4} Msun  drift {:.2e}", units::mass_to_solar(now.mass as Real), drift.mass),
+                grade(drift.mass)),
+            (format!("Speed limit ({}) stepped in {} times", universe.physics_engine.velocity_policy.name(),
+                universe.diagnostics.speed_limited),
+                if universe.diagnostics.speed_limited == 0 { Color::new(0.6, 1.0, 0.6, 1.0) } else { Color::new(1.0, 0.9, 0.5, 1.0) }),
+            (format!("Over {}", units::format_time(universe.diagnostics.elapsed)), WHITE),
+        ];
+        for (i, (line, color)) in lines.iter().enumerate() {
+            draw_text(line, box_x + 10.0, box_y + 22.0 + i as f32 * 21.0, 16.0, *color);
+        }
+    }
+    
+    // Latest events in the bottom left corner, newest on top
+    pub fn draw_events(&self, universe: &Universe, screen_height: f32) {
+        let lines: Vec<String> = universe.events.recent(5)
+            .map(|event| format!("{}  {}", units::format_time(event.age), event.description()))
+            .collect();
+        if lines.is_empty() {
+            return;
+        }
+        
+        let start_y = screen_height - 20.0 - lines.len() as f32 * 20.0;
+        for (i, line) in lines.iter().enumerate() {
+            let alpha = 1.0 - i as f32 * 0.15;
+            draw_text(line, 20.0, start_y + i as f32 * 20.0, 16.0, Color::new(1.0, 0.7, 0.9, alpha));
         }
     }
     
@@ -103,8 +295,9 @@ impl Renderer {
         }
     }
     
-    // Draw orbital paths 
-    fn draw_stable_orbits(&self, objects: &[CelestialObject], center_x: f32, center_y: f32, zoom: f32) {
+    // Draw orbital paths, each ring in the plane the body is actually moving in
+    fn draw_stable_orbits(&self, particles: &ParticleStore, camera: &Camera) {
+        let zoom = camera.zoom;
         let base_line_thickness = if zoom > 3.0 {
             1.2
         } else if zoom > 2.0 {
@@ -124,21 +317,22 @@ impl Renderer {
         };
         
         // Find stars and draw orbits for their planets
-        for star in objects.iter().filter(|obj| matches!(obj.object_type, ObjectType::Star | 
+        for (star, _) in particles.iter().filter(|(_, obj)| matches!(obj.object_type, ObjectType::Star | 
             ObjectType::WhiteDwarf | 
             ObjectType::NeutronStar | 
             ObjectType::Pulsar)) {
-            let star_x = center_x + star.x * zoom;
-            let star_y = center_y + star.y * zoom;
+            let star_centre = (star.x, star.y, star.z);
             
-            for planet in objects.iter().filter(|obj| matches!(obj.object_type, ObjectType::Planet)) {
+            for (planet, _) in particles.iter().filter(|(_, obj)| matches!(obj.object_type, ObjectType::Planet)) {
                 let dx = planet.x - star.x;
                 let dy = planet.y - star.y;
-                let distance = (dx * dx + dy * dy).sqrt();
+                let dz = planet.z - star.z;
+                let distance = to_f32((dx * dx + dy * dy + dz * dz).sqrt());
                 
                 // Only draw orbits for planets that are actually orbiting this star
                 if distance < 80.0 && distance > 8.0 {
                     let orbit_radius = distance * zoom;
+                    let normal = orbit_normal((dx, dy, dz), (planet.vx - star.vx, planet.vy - star.vy, planet.vz - star.vz));
                     
                     // Orbit color based on distance from star
                     let orbit_color = if distance < 30.0 {
@@ -162,12 +356,9 @@ impl Renderer {
                     };
                     
                     // Draw the main orbit circle
-                    draw_circle_lines(
-                        star_x, star_y, 
-                        orbit_radius, 
-                        line_thickness,
-                        orbit_color
-                    );
+                    let world_radius = (dx * dx + dy * dy + dz * dz).sqrt();
+                    let ring = Ring { centre: star_centre, radius: world_radius, normal };
+                    self.draw_ring(camera, ring, line_thickness, orbit_color);
                     
                     // Add a subtle inner ring (optional)
                     if zoom > 2.5 && line_thickness > 0.5 {
@@ -178,22 +369,13 @@ impl Renderer {
                             orbit_color.a * 0.7
                         );
                         
-                        draw_circle_lines(
-                            star_x, star_y,
-                            orbit_radius * 0.99,
-                            line_thickness * 0.7,
-                            inner_orbit_color
-                        );
+                        let inner = Ring { radius: world_radius * 0.99, ..ring };
+                        self.draw_ring(camera, inner, line_thickness * 0.7, inner_orbit_color);
                     }
                     
                     // Add dots on orbit for better visibility when really zoomed in
                     if zoom > 4.0 && orbit_radius > 20.0 {
-                        let point_count = 12;
-                        for i in 0..point_count {
-                            let angle = i as f32 * 2.0 * std::f32::consts::PI / point_count as f32;
-                            let point_x = star_x + orbit_radius * angle.cos();
-                            let point_y = star_y + orbit_radius * angle.sin();
-                            
+                        for (point_x, point_y) in ring_points(camera, ring, 12).into_iter().flatten() {
                             draw_circle(
                                 point_x, point_y,
                                 1.5,
@@ -206,89 +388,74 @@ impl Renderer {
             
             // Add galactic orbits for stars far from center
             if zoom > 1.0 {
-                let dx = star.x;
-                let dy = star.y;
-                let distance_from_center = (dx * dx + dy * dy).sqrt();
+                let distance = (star.x * star.x + star.y * star.y + star.z * star.z).sqrt();
+                let distance_from_center = to_f32(distance);
                 
                 // If star is far from galactic center, show its galactic orbit
                 if distance_from_center > 200.0 && distance_from_center < 800.0 {
-                    let galactic_orbit_radius = distance_from_center * zoom;
                     let galactic_color = Color::new(0.5, 0.3, 0.8, 0.08);  // Faint purple
+                    let normal = orbit_normal((star.x, star.y, star.z), (star.vx, star.vy, star.vz));
                     
-                    draw_circle_lines(
-                        center_x, center_y,
-                        galactic_orbit_radius,
-                        0.3,
-                        galactic_color
-                    );
+                    let ring = Ring { centre: (0.0, 0.0, 0.0), radius: distance, normal };
+                    self.draw_ring(camera, ring, 0.3, galactic_color);
                 }
             }
         }
         
         // Add binary star orbits
         if zoom > 2.0 {
-            self.draw_binary_orbits(objects, center_x, center_y, zoom);
+            self.draw_binary_orbits(particles, camera);
         }
     }
     
     // Draw orbits for binary star systems
-    fn draw_binary_orbits(&self, objects: &[CelestialObject], center_x: f32, center_y: f32, zoom: f32) {
-        let stars: Vec<_> = objects.iter()
-            .filter(|obj| matches!(obj.object_type, ObjectType::Star | 
+    fn draw_binary_orbits(&self, particles: &ParticleStore, camera: &Camera) {
+        let stars: Vec<Particle> = particles.iter()
+            .filter(|(_, obj)| matches!(obj.object_type, ObjectType::Star | 
                 ObjectType::WhiteDwarf | 
                 ObjectType::NeutronStar))
+            .map(|(star, _)| star)
             .collect();
         
         for i in 0..stars.len() {
             for j in (i + 1)..stars.len() {
-                let star1 = stars[i];
-                let star2 = stars[j];
+                let star1 = &stars[i];
+                let star2 = &stars[j];
                 
                 let dx = star2.x - star1.x;
                 let dy = star2.y - star1.y;
-                let distance = (dx * dx + dy * dy).sqrt();
+                let dz = star2.z - star1.z;
+                let separation = (dx * dx + dy * dy + dz * dz).sqrt();
+                let distance = to_f32(separation);
                 
                 // If stars are close together, draw a binary orbit
                 if distance < 40.0 && distance > 10.0 {
-                    let center_x_world = (star1.x + star2.x) / 2.0;
-                    let center_y_world = (star1.y + star2.y) / 2.0;
-                    
-                    let screen_center_x = center_x + center_x_world * zoom;
-                    let screen_center_y = center_y + center_y_world * zoom;
-                    
-                    let orbit_radius = distance * zoom / 2.0;
+                    let centre = ((star1.x + star2.x) / 2.0, (star1.y + star2.y) / 2.0, (star1.z + star2.z) / 2.0);
+                    let normal = orbit_normal((dx, dy, dz), (star2.vx - star1.vx, star2.vy - star1.vy, star2.vz - star1.vz));
                     
                     // Binary orbit - green
-                    draw_circle_lines(
-                        screen_center_x, screen_center_y,
-                        orbit_radius,
-                        0.4,
-                        Color::new(0.4, 0.8, 0.4, 0.2)
-                    );
+                    let ring = Ring { centre, radius: separation / 2.0, normal };
+                    self.draw_ring(camera, ring, 0.4, Color::new(0.4, 0.8, 0.4, 0.2));
                     
                     // Connecting line between stars
-                    let star1_x = center_x + star1.x * zoom;
-                    let star1_y = center_y + star1.y * zoom;
-                    let star2_x = center_x + star2.x * zoom;
-                    let star2_y = center_y + star2.y * zoom;
-                    
-                    draw_line(
-                        star1_x, star1_y,
-                        star2_x, star2_y,
-                        0.3,
-                        Color::new(0.4, 0.8, 0.4, 0.15)
-                    );
+                    if let (Some(first), Some(second)) = (camera.project_object(star1), camera.project_object(star2)) {
+                        draw_line(
+                            first.x, first.y,
+                            second.x, second.y,
+                            0.3,
+                            Color::new(0.4, 0.8, 0.4, 0.15)
+                        );
+                    }
                 }
             }
         }
     }
     
-    // Draw a single celestial object
-    fn draw_stable_object(&self, obj: &CelestialObject, center_x: f32, center_y: f32, zoom: f32) {
-        let screen_x = center_x + obj.x * zoom;
-        let screen_y = center_y + obj.y * zoom;
-        let base_radius = obj.radius * zoom;
-        let display_radius = base_radius.max(0.3).min(50.0);  // Don't get too big or small
+    // Draw a single celestial object where the camera put it
+    fn draw_stable_object(&self, body: &Particle, obj: &CelestialObject, point: Projected) {
+        let (screen_x, screen_y, zoom) = (point.x, point.y, point.scale);
+        let base_radius = to_f32(obj.radius) * zoom;
+        let display_radius = base_radius.clamp(0.3, 50.0);  // Don't get too big or small
         
         let color = self.get_object_color(obj);
         
@@ -306,7 +473,7 @@ impl Renderer {
                 draw_circle(screen_x, screen_y, display_radius, color);
                 
                 // Add a ring for larger planets
-                if display_radius > 1.5 && obj.mass > 200.0 {
+                if display_radius > 1.5 && body.mass > 200.0 {
                     draw_circle_lines(screen_x, screen_y, display_radius * 1.2, 0.5,
                         Color::new(color.r * 0.8, color.g * 0.8, color.b * 0.8, 0.4));
                 }
@@ -319,7 +486,7 @@ impl Renderer {
                 draw_circle(screen_x, screen_y, display_radius * 1.5, glow_color);
             }
             ObjectType::BlackHole => {
-                let event_horizon = (obj.event_horizon_radius * zoom).max(1.2);
+                let event_horizon = (to_f32(obj.event_horizon_radius) * zoom).max(1.2);
                 draw_circle(screen_x, screen_y, event_horizon, BLACK);
                 
                 // Add accretion disk for larger black holes
@@ -328,14 +495,8 @@ impl Renderer {
                     draw_circle(screen_x, screen_y, event_horizon * 2.0, disk_color);
                 }
             }
-            ObjectType::DarkMatter => {
-                // Dark matter is mysterious - just show an outline
-                if display_radius > 1.0 {
-                    draw_circle_lines(screen_x, screen_y, display_radius, 0.5, color);
-                }
-            }
             ObjectType::DarkEnergy => {
-                // Dark energy is even more mysterious - tiny dots
+                // Dark energy is mysterious - tiny dots
                 if display_radius > 0.5 {
                     draw_circle(screen_x, screen_y, display_radius * 0.3, color);
                 }
@@ -346,6 +507,10 @@ impl Renderer {
             ObjectType::Asteroid => {
                 draw_circle(screen_x, screen_y, display_radius, color);
             }
+            ObjectType::Dust => {
+                // Far too small to see, so a speck at any zoom
+                draw_circle(screen_x, screen_y, display_radius.max(0.8), color);
+            }
             ObjectType::WhiteDwarf => {
                 draw_circle(screen_x, screen_y, display_radius, color);
             }
@@ -359,27 +524,25 @@ impl Renderer {
     }
     //----------------------------------
     // Draw faint lines between close objects
-    fn draw_object_connections(&self, objects: &[CelestialObject], center_x: f32, center_y: f32, zoom: f32) {
-        for i in 0..objects.len() {
-            for j in (i + 1)..objects.len() {
-                let obj1 = &objects[i];
-                let obj2 = &objects[j];
+    fn draw_object_connections(&self, particles: &ParticleStore, camera: &Camera) {
+        for i in 0..particles.len() {
+            for j in (i + 1)..particles.len() {
+                let obj1 = particles.particle(i);
+                let obj2 = particles.particle(j);
                 
                 let dx = obj2.x - obj1.x;
                 let dy = obj2.y - obj1.y;
-                let distance = (dx * dx + dy * dy).sqrt();
+                let dz = obj2.z - obj1.z;
+                let distance = to_f32((dx * dx + dy * dy + dz * dz).sqrt());
                 
                 // Only connect objects that are somewhat close
-                if distance < 30.0 && distance > 5.0 {
-                    let x1 = center_x + obj1.x * zoom;
-                    let y1 = center_y + obj1.y * zoom;
-                    let x2 = center_x + obj2.x * zoom;
-                    let y2 = center_y + obj2.y * zoom;
-                    
+                if distance < 30.0 && distance > 5.0
+                    && let (Some(first), Some(second)) = (camera.project_object(&obj1), camera.project_object(&obj2))
+                {
                     let strength = 1.0 / distance;
                     let alpha = (strength * 0.2).min(0.1);
                     
-                    draw_line(x1, y1, x2, y2, 0.3,
+                    draw_line(first.x, first.y, second.x, second.y, 0.3,
                         Color::new(0.3, 0.5, 0.8, alpha));
                 }
             }
@@ -402,7 +565,6 @@ impl Renderer {
                 Color::new(r, g, b, 1.0)
             }
             ObjectType::GalaxyCenter => Color::new(1.0, 1.0, 0.7, 1.0),  // Yellowish
-            ObjectType::DarkMatter => Color::new(0.3, 0.3, 0.6, 0.2),    // Dark purple, faint
             ObjectType::DarkEnergy => Color::new(0.6, 0.2, 0.7, 0.1),    // Purple, very faint
             ObjectType::BlackHole => Color::new(0.0, 0.0, 0.0, 1.0),     // Well, black
             ObjectType::WhiteDwarf => Color::new(0.9, 0.9, 1.0, 1.0),    // White-blue
@@ -410,6 +572,7 @@ impl Renderer {
             ObjectType::Pulsar => Color::new(0.7, 0.8, 1.0, 1.0),        // Blue with hint of white
             ObjectType::Comet => Color::new(0.7, 0.8, 1.0, 1.0),         // Icy blue
             ObjectType::Asteroid => Color::new(0.5, 0.5, 0.5, 1.0),      // Gray rock
+            ObjectType::Dust => Color::new(0.85, 0.75, 0.6, 0.7),        // Faint sunlit tan
         }
     }
     
@@ -484,7 +647,8 @@ impl Renderer {
     }
     
     // Draw detailed information modal
-    pub fn draw_detail_modal(&self, obj: &CelestialObject, show_modal: &mut bool, screen_width: f32, screen_height: f32) {
+    pub fn draw_detail_modal(&self, body: &Particle, obj: &CelestialObject, show_modal: &mut bool,
+                             screen_width: f32, screen_height: f32) {
         // Semi-transparent overlay
         draw_rectangle(0.0, 0.0, screen_width, screen_height, Color::new(0.0, 0.0, 0.0, 0.7));
         
@@ -503,7 +667,6 @@ impl Renderer {
             ObjectType::Star => "STAR - Detailed Information",
             ObjectType::Planet => "PLANET - Detailed Information",
             ObjectType::GalaxyCenter => "GALAXY CENTER - Detailed Information",
-            ObjectType::DarkMatter => "DARK MATTER - Detailed Information",
             ObjectType::DarkEnergy => "DARK ENERGY - Detailed Information",
             ObjectType::BlackHole => "BLACK HOLE - Detailed Information",
             ObjectType::NeutronStar => "NEUTRON STAR - Detailed Information",
@@ -511,6 +674,7 @@ impl Renderer {
             ObjectType::WhiteDwarf => "WHITE DWARF - Detailed Information",
             ObjectType::Comet => "COMET - Detailed Information",
             ObjectType::Asteroid => "ASTEROID - Detailed Information",
+            ObjectType::Dust => "DUST GRAIN - Detailed Information",
         };
         
         // Draw centered title
@@ -525,7 +689,7 @@ impl Renderer {
         );
         
         // Get and drw detailed description
-        let description = self.get_detailed_description(obj);
+        let description = self.get_detailed_description(body, obj);
         let font_size = if modal_width < 500.0 { 16.0 } else { 18.0 };
         
         // Draw wrappeddescription
@@ -576,7 +740,7 @@ impl Renderer {
     }
     
     // Get detailed scientific description for each object type
-    fn get_detailed_description(&self, obj: &CelestialObject) -> String {
+    fn get_detailed_description(&self, body: &Particle, obj: &CelestialObject) -> String {
         match obj.object_type {
             ObjectType::Star => format!(
                 "STARS are massive, luminous spheres of plasma held together by gravity.\n\n\
@@ -589,7 +753,7 @@ impl Renderer {
                 (5,000-6,000 K), and red stars are coolest (3,000-4,000 K).\n\n\
                 This star will eventually exhaust its nuclear fuel and evolve into a\n\
                 red giant, then a planetary nebula, leaving behind a white dwarf remnant.",
-                obj.mass / 2e5, obj.temperature
+                units::mass_to_solar(body.mass), obj.temperature
             ),
             
             ObjectType::Planet => format!(
@@ -602,7 +766,7 @@ impl Renderer {
                 where dust and gas coalesce through accretion. Planets play crucial roles\n\
                 in their systems, influencing asteroid distribution and potentially\n\
                 supporting life through stable orbital conditions.",
-                obj.mass / 100.0,
+                units::mass_to_earth(body.mass),
                 obj.temperature,
                 if obj.temperature > 400.0 { "a gas giant." } else { "a terrestrial planet." }
             ),
@@ -611,28 +775,32 @@ impl Renderer {
                 "BLACK HOLES are regions of spacetime where gravity is so strong that\n\
                 nothing, not even light, can escape.\n\n\
                 This black hole has a mass of {:.1} solar masses with an event horizon\n\
-                radius of {:.2} km.\n\n\
+                radius of {:.0} km.\n\n\
                 Black holes form when massive stars collapse at the end of their life\n\
                 cycles or through the merger of other compact objects. They are characterized\n\
                 by their event horizon - the point of no return.\n\n\
-                This appears to be a {} black hole. Black holes warp spacetime,\n\
+                This appears to be {} black hole. Black holes warp spacetime,\n\
                 cause gravitational lensing, and can have accretion disks of hot matter\n\
                 spiraling into them.",
-                obj.mass / 2e5,
-                obj.event_horizon_radius / 1000.0,
-                if obj.mass > 1e6 { "supermassive" } else { "stellar-mass" }
+                units::mass_to_solar(body.mass),
+                units::length_to_km(obj.event_horizon_radius),
+                match units::mass_to_solar(body.mass) {
+                    solar if solar >= 1e5 => "a supermassive",
+                    solar if solar >= 100.0 => "an intermediate-mass",
+                    _ => "a stellar-mass",
+                }
             ),
             
             ObjectType::NeutronStar => format!(
                 "NEUTRON STARS are the collapsed cores of massive stars that have\n\
                 undergone supernova explosions.\n\n\
-                This neutron star has a mass of {:.1} solar masses compressed into\n\
-                a sphere only {:.1} km in radius. It rotates every {:.3} seconds.\n\n\
+                This neutron star has a mass of {:.1} solar masses. Real ones are only about\n\
+                10 km in radius; this one is drawn {} across. It rotates every {:.3} seconds.\n\n\
                 Neutron stars are incredibly dense - one teaspoon would weigh billions\n\
                 of tons. They are supported against further collapse by neutron degeneracy\n\
                 pressure. Some neutron stars emit beams of radiation, becoming pulsars.",
-                obj.mass / 2e5,
-                obj.radius,
+                units::mass_to_solar(body.mass),
+                units::format_length(2.0 * obj.radius),
                 obj.rotation_period
             ),
             
@@ -651,14 +819,14 @@ impl Renderer {
             ObjectType::WhiteDwarf => format!(
                 "WHITE DWARFS are the stellar remnants left after low and medium mass stars\n\
                 exhaust their nuclear fuel.\n\n\
-                This white dwarf has a mass of {:.1} solar masses packed into a sphere\n\
-                only {:.1} Earth radii in size, with a temperature of {:.0} K.\n\n\
+                This white dwarf has a mass of {:.1} solar masses and a temperature of {:.0} K.\n\
+                Real ones are about the size of the Earth; this one is drawn {} across.\n\n\
                 White dwarfs are supported by electron degeneracy pressure and have\n\
                 densities of about 1 ton per cubic centimeter. They slowly cool over\n\
                 billions of years.",
-                obj.mass / 2e5,
-                obj.radius / 0.5,
-                obj.temperature
+                units::mass_to_solar(body.mass),
+                obj.temperature,
+                units::format_length(2.0 * obj.radius)
             ),
             
             ObjectType::Comet => format!(
@@ -668,18 +836,31 @@ impl Renderer {
                 Comets are composed of frozen gases, rock, and dust. They originate\n\
                 from the Kuiper Belt and Oort Cloud in the outer Solar System.\n\
                 When heated by the Sun, comets develop spectacular tails.",
-                (obj.vx * obj.vx + obj.vy * obj.vy).sqrt() / 1000.0,
+                units::speed_to_km_per_s((body.vx * body.vx + body.vy * body.vy + body.vz * body.vz).sqrt()),
                 obj.temperature
             ),
             
             ObjectType::Asteroid => format!(
                 "ASTEROIDS are minor planets of the inner Solar System, ranging in size\n\
                 from about 1 meter to hundreds of kilometers.\n\n\
-                This asteroid has a mass of {:.1} tons and is traveling at {:.1} km/s.\n\n\
+                This asteroid has a mass of {:.2e} tons and is traveling at {:.1} km/s.\n\n\
                 Most asteroids are found in the asteroid belt between Mars and Jupiter.\n\
                 They are remnants from the Solar System's formation that never coalesced into planets.",
-                obj.mass,
-                (obj.vx * obj.vx + obj.vy * obj.vy).sqrt() / 1000.0
+                units::mass_to_kg(body.mass) / 1000.0,
+                units::speed_to_km_per_s((body.vx * body.vx + body.vy * body.vy + body.vz * body.vz).sqrt())
+            ),
+            
+            ObjectType::Dust => format!(
+                "DUST GRAINS are specks of rock and ice from micrometres to millimetres\n\
+                across, shed by comets and left over from planet formation.\n\n\
+                This grain has a cross-section of {:.2e} m² per kg (as drawn)\n\
+                and is traveling at {:.1} km/s.\n\n\
+                Grains this small feel the pressure of starlight as well as gravity. The\n\
+                smallest are blown out of their systems, which is why comet dust tails point\n\
+                away from the Sun. Larger ones are slowed by Poynting-Robertson drag and\n\
+                spiral slowly into their star.",
+                std::f64::consts::PI * units::length_to_m(obj.radius).powi(2) / units::mass_to_kg(body.mass),
+                units::speed_to_km_per_s((body.vx * body.vx + body.vy * body.vy + body.vz * body.vz).sqrt())
             ),
             
             ObjectType::GalaxyCenter => format!(
@@ -688,17 +869,7 @@ impl Renderer {
                 This galactic center has a mass of {:.1} solar masses.\n\n\
                 The centers of galaxies are sites of intense activity, with stars\n\
                 moving at high velocities and often showing evidence of past mergers.",
-                obj.mass / 2e5
-            ),
-            
-            ObjectType::DarkMatter => format!(
-                "DARK MATTER is a hypothetical form of matter that is thought to account\n\
-                for approximately 85% of the matter in the universe.\n\n\
-                This dark matter clump has a mass of {:.1} solar masses but interacts\n\
-                only through gravity.\n\n\
-                Dark matter does not emit, absorb, or reflect light, making it invisible\n\
-                to electromagnetic observations.",
-                obj.mass / 2e5
+                units::mass_to_solar(body.mass)
             ),
             
             ObjectType::DarkEnergy => format!(
@@ -708,19 +879,19 @@ impl Renderer {
                 Dark energy is the dominant component of the universe (about 68% of\n\
                 the total energy density). Its existence was inferred from observations\n\
                 that the universe's expansion is accelerating.",
-                obj.mass / 1e6
+                body.mass / 1e6
             ),
         }
     }
     
     // Draw controls/help panel
-    pub fn draw_help(&self, expansion_speed: f32, universe_age: f64, zoom: f32, 
-        offset_x: f32, offset_y: f32, time_scale: &str, 
-        fullscreen: bool, screen_width: f32, screen_height: f32) {
+    pub fn draw_help(&self, universe: &Universe, hud: &HudState) {
+        let HudState { expansion_speed, zoom, time_scale, fullscreen, screen_width, screen_height } = *hud;
+        let universe_age = universe.age;
 
         // Mini help for tiny screens
         if screen_width < 600.0 {
-            self.draw_minimal_help(expansion_speed, universe_age, zoom, time_scale, fullscreen, screen_width);
+            self.draw_minimal_help(universe, hud);
             return;
         }
 
@@ -730,11 +901,7 @@ impl Renderer {
             _ => "Medium",
         };
 
-        let age_display = if universe_age < 1.0 {
-            format!("{:.2} years", universe_age * 1e6)
-        } else {
-            format!("{:.1} Myr", universe_age)
-        };
+        let age_display = units::format_time(universe_age);
 
         // Help text (including F5)
         let help_lines = [
@@ -744,7 +911,7 @@ impl Renderer {
             &format!("Time: {} (Space: Pause)", time_scale),
             "Shift: Slow Motion",
             "Zoom: Mouse Wheel",
-            "Drag Camera: Right Click",
+            if universe.three_d { "Orbit Camera: Right Click or Arrows" } else { "Drag Camera: Right Click" },
             "Reset: R or Middle Click",
             "Select: Left Click",
             "Details: Tab (when object selected)",
@@ -752,6 +919,51 @@ impl Renderer {
             &format!("Fullscreen: F (Now: {})", if fullscreen { "ON" } else { "OFF" }),
             &format!("Age: {}", age_display),
             &format!("Zoom: {:.1}x", zoom),
+            &match universe.physics_engine.gravity_engine.accuracy() {
+                Some(accuracy) => format!("Gravity: {} ({}) (G: Switch, [ ]: Accuracy)",
+                    universe.physics_engine.gravity_engine.solver.name(), accuracy),
+                None => format!("Gravity: {} (G: Switch)", universe.physics_engine.gravity_engine.solver.name()),
+            },
+            &format!("Boundary: {} (B: Toggle)", universe.boundary.name()),
+            &format!("Softening: {} (eps = {}) (K: Switch)",
+                universe.physics_engine.gravity_engine.softening_kernel.name(),
+                units::format_length(universe.physics_engine.gravity_engine.softening_length)),
+            &format!("Post-Newtonian: {} (c = {}) (P: Toggle)",
+                if universe.physics_engine.gravity_engine.post_newtonian { "ON" } else { "OFF" },
+                units::format_speed(universe.physics_engine.gravity_engine.speed_of_light)),
+            &format!("Integrator: {} x{} (I: Switch)", universe.physics_engine.integrator.name(),
+                universe.physics_engine.force_evaluations),
+            &format!("Speed Limit: {} ({}) (L: Switch)", universe.physics_engine.velocity_policy.name(),
+                match universe.physics_engine.velocity_policy {
+                    VelocityPolicy::Off => "none".to_string(),
+                    VelocityPolicy::Clamp => units::format_speed(universe.physics_engine.max_speed),
+                    VelocityPolicy::Relativistic =>
+                        format!("c = {}", units::format_speed(universe.physics_engine.gravity_engine.speed_of_light)),
+                }),
+            &format!("Collisions: {} ({} merged, {} bounced, {} shattered) (C: Switch)",
+                universe.collision_engine.mode.name(), universe.collision_engine.merges,
+                universe.collision_engine.bounces, universe.collision_engine.shatters),
+            &format!("Fragmentation: {} (X: Toggle)",
+                if universe.collision_engine.fragmentation { "ON" } else { "OFF" }),
+            &format!("Radiation Reaction: {} ({} merged) (W: Toggle)",
+                if universe.physics_engine.gravity_engine.radiation_reaction { "ON" } else { "OFF" },
+                universe.gravitational_waves.mergers),
+            "Giant Impact Demo: M  Black Hole Binary: N",
+            "Conservation Diagnostics: D",
+            &format!("3D Mode: {} (V: Toggle)", if universe.three_d { "ON" } else { "OFF" }),
+            &format!("Dark Matter Halo: {} (H: Switch)", universe.halo_profile.name()),
+            &format!("Radiation: {} (O: Switch)", universe.radiation_engine.mode.name()),
+            &format!("Comet Dust: {} ({} grains shed) (J: Toggle)",
+                if universe.radiation_engine.comet_dust { "ON" } else { "OFF" }, universe.radiation_engine.grains_shed),
+            &format!("Force Fields: {}", if universe.physics_engine.force_fields.is_empty() {
+                "None".to_string()
+            } else {
+                universe.physics_engine.force_fields.iter().map(|field| field.name()).collect::<Vec<_>>().join(", ")
+            }),
+            &format!("Accretion: {} ({} swallowed) (A: Toggle)",
+                if universe.accretion_engine.enabled { "ON" } else { "OFF" }, universe.accretion_engine.swallowed),
+            &format!("Tidal Disruption: {} ({} torn) (T: Toggle)",
+                if universe.tidal_engine.enabled { "ON" } else { "OFF" }, universe.tidal_engine.disruptions),
         ];
 
         let start_x = screen_width - 360.0;
@@ -788,8 +1000,8 @@ impl Renderer {
     }
 
     // Mini help for small screens
-    fn draw_minimal_help(&self, expansion_speed: f32, universe_age: f64, zoom: f32, 
-                time_scale: &str, fullscreen: bool, screen_width: f32) {
+    fn draw_minimal_help(&self, universe: &Universe, hud: &HudState) {
+        let HudState { expansion_speed, zoom, time_scale, screen_width, .. } = *hud;
         let essential_lines = [
             "CONTROLS (Minimal)",
             &format!("Exp: {} | Time: {}", 
@@ -800,11 +1012,11 @@ impl Renderer {
                 },
                 time_scale
             ),
-            "Zoom: Wheel | Drag: Right",
+            if universe.three_d { "Zoom: Wheel | Orbit: Right" } else { "Zoom: Wheel | Drag: Right" },
             "Select: Left | Reset: R",
             "Details: Tab",
             "Fullscreen: F",
-            &format!("Age: {:.1}M | Zoom: {:.1}x", universe_age, zoom),
+            &format!("Age: {} | Zoom: {:.1}x", units::format_time(universe.age), zoom),
         ];
 
         let start_x = screen_width - 200.0;
@@ -829,4 +1041,44 @@ impl Renderer {
             );
         }
     }
-}
\ No newline at end of file
+}
+// Axis of the orbit of something at r moving with v relative to what it circles (r x v).
+// Straight out of the screen if that's undefined
+fn orbit_normal(r: (Real, Real, Real), v: (Real, Real, Real)) -> (Real, Real, Real) {
+    let normal = (r.1 * v.2 - r.2 * v.1, r.2 * v.0 - r.0 * v.2, r.0 * v.1 - r.1 * v.0);
+    let length = (normal.0 * normal.0 + normal.1 * normal.1 + normal.2 * normal.2).sqrt();
+    if length > 0.0 {
+        (normal.0 / length, normal.1 / length, normal.2 / length)
+    } else {
+        (0.0, 0.0, 1.0)
+    }
+}
+
+// A circle in space around `centre`, in the plane at right angles to `normal`
+#[derive(Clone, Copy)]
+struct Ring {
+    centre: (Real, Real, Real),
+    radius: Real,
+    normal: (Real, Real, Real),
+}
+
+// `count` evenly spaced screen points on a ring (see draw_ring)
+fn ring_points(camera: &Camera, ring: Ring, count: usize) -> Vec<Option<(f32, f32)>> {
+    let Ring { centre, radius, normal } = ring;
+    // Two unit vectors spanning the circle's plane: u = n x the x axis (the y axis if n is
+    // close to x), and w = n x u
+    let (nx, ny, nz) = normal;
+    let u = if nx.abs() < 0.9 { (0.0, nz, -ny) } else { (-nz, 0.0, nx) };
+    let u_length = (u.0 * u.0 + u.1 * u.1 + u.2 * u.2).sqrt();
+    let u = (u.0 / u_length, u.1 / u_length, u.2 / u_length);
+    let w = (ny * u.2 - nz * u.1, nz * u.0 - nx * u.2, nx * u.1 - ny * u.0);
+
+    (0..count).map(|k| {
+        let (sin, cos) = (k as Real * 2.0 * consts::PI / count as Real).sin_cos();
+        camera.project(
+            centre.0 + radius * (u.0 * cos + w.0 * sin),
+            centre.1 + radius * (u.1 * cos + w.1 * sin),
+            centre.2 + radius * (u.2 * cos + w.2 * sin),
+        ).map(|point| (point.x, point.y))
+    }).collect()
+}
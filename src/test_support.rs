@@ -0,0 +1,24 @@
+// Checks the unit tests of several engines share
+use crate::particles::ParticleStore;
+use crate::real::to_f64;
+
+// Mass, momentum and mass-weighted position, in f64, each with the sum of the sizes
+// of its terms to compare round-off against
+pub fn totals(particles: &ParticleStore) -> [(f64, f64); 7] {
+    let mut sums = [(0.0, 0.0); 7];
+    for (obj, _) in particles.iter() {
+        let m = to_f64(obj.mass);
+        let values = [1.0, to_f64(obj.vx), to_f64(obj.vy), to_f64(obj.vz), to_f64(obj.x), to_f64(obj.y), to_f64(obj.z)];
+        for (sum, value) in sums.iter_mut().zip(values) {
+            *sum = (sum.0 + m * value, sum.1 + (m * value).abs());
+        }
+    }
+    sums
+}
+
+// Every one of the totals is where it was, up to round-off
+pub fn assert_kept(before: [(f64, f64); 7], after: [(f64, f64); 7]) {
+    for (k, ((a, scale), (b, _))) in before.into_iter().zip(after).enumerate() {
+        assert!((a - b).abs() <= 1e-5 * scale, "total {k}: {a} became {b}");
+    }
+}
@@ -0,0 +1,171 @@
+use crate::collisions::swept_contact;
+use crate::events::{EventKind, EventLog, SimulationEvent};
+use crate::objects::{CelestialObject, ObjectType};
+use crate::particles::{Body, Particle, ParticleStore};
+use crate::units;
+use crate::real::Real;
+
+// Stars and planets that wander inside the Roche limit of something much heavier
+// are pulled apart by the difference in gravity across them
+pub struct TidalEngine {
+    pub enabled: bool,
+    // Roche limit d = k R (M / m)^(1/3) for a body of radius R and mass m near a mass M;
+    // k = 2.44 for a fluid body that deforms as it's stretched
+    pub roche_coefficient: Real,
+    pub min_mass_ratio: Real,    // M has to be at least this many times m
+    pub massive_star_mass: Real, // stars only count as disruptors above this mass
+    pub debris_count: usize,    // pieces per disrupted body
+    pub disruptions: u32,       // bodies torn apart so far
+}
+
+impl TidalEngine {
+    pub fn new() -> Self {
+        TidalEngine {
+            enabled: true,
+            roche_coefficient: 2.44,
+            min_mass_ratio: 100.0,
+            massive_star_mass: units::solar_masses(50.0), // twice the heaviest solar system star
+            debris_count: 20,
+            disruptions: 0,
+        }
+    }
+
+    // Roche limit of primary for body, both indices into the store
+    pub fn roche_limit(&self, particles: &ParticleStore, primary: usize, body: usize) -> Real {
+        self.roche_coefficient * particles.objects[body].radius * (particles.mass[primary] / particles.mass[body]).cbrt()
+    }
+
+    // Tear apart every star or planet that crossed into a disruptor's Roche limit during
+    // the last step of length dt. Bodies that start out inside the limit are left alone;
+    // only the plunge in triggers a disruption. Returns true if anything was torn apart
+    pub fn disrupt(&mut self, particles: &mut ParticleStore, dt: Real, age: f64, events: &mut EventLog) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let primaries: Vec<usize> = (0..particles.len()).filter(|&i| self.is_disruptor(particles, i)).collect();
+        if primaries.is_empty() {
+            return false;
+        }
+
+        let mut torn = vec![false; particles.len()];
+        let mut debris = Vec::new();
+        for &p in &primaries {
+            for i in 0..particles.len() {
+                let victim = particles.objects[i].object_type;
+                if i == p || torn[i] || torn[p] || !matches!(victim, ObjectType::Star | ObjectType::Planet)
+                    || particles.mass[p] < self.min_mass_ratio * particles.mass[i]
+                {
+                    continue;
+                }
+
+                let (primary, body) = (particles.particle(p), particles.particle(i));
+                let limit = self.roche_limit(particles, p, i);
+                let start_x = (body.x - body.vx * dt) - (primary.x - primary.vx * dt);
+                let start_y = (body.y - body.vy * dt) - (primary.y - primary.vy * dt);
+                let start_z = (body.z - body.vz * dt) - (primary.z - primary.vz * dt);
+                let was_outside = start_x * start_x + start_y * start_y + start_z * start_z > limit * limit;
+                if !was_outside || swept_contact(&primary, &body, limit, dt).is_none() {
+                    continue;
+                }
+
+                debris.extend(self.shred(&primary, &body, particles.objects[i].radius, age));
+                events.push(SimulationEvent {
+                    age,
+                    x: body.x,
+                    y: body.y,
+                    z: body.z,
+                    kind: EventKind::TidalDisruption {
+                        victim,
+                        mass: body.mass,
+                        disruptor: particles.objects[p].object_type,
+                    },
+                });
+                torn[i] = true;
+                self.disruptions += 1;
+            }
+        }
+
+        if debris.is_empty() {
+            return false;
+        }
+        particles.retain(|index| !torn[index]);
+        particles.extend(debris);
+        true
+    }
+
+    fn is_disruptor(&self, particles: &ParticleStore, i: usize) -> bool {
+        match particles.objects[i].object_type {
+            ObjectType::BlackHole | ObjectType::NeutronStar | ObjectType::Pulsar => true,
+            ObjectType::Star => particles.mass[i] >= self.massive_star_mass,
+            _ => false,
+        }
+    }
+
+    // Split the body into a line of equal pieces along the direction to the disruptor,
+    // all moving with the body's velocity. Pieces on the near side sit deeper in the
+    // potential than pieces on the far side, so the frozen-in spread of orbital energy
+    // stretches them into a stream: the near half stays bound and falls back, the far
+    // half is flung out. Mass, momentum and centre of mass are unchanged
+    fn shred(&self, primary: &Particle, body: &Particle, body_radius: Real, age: f64) -> Vec<Body> {
+        let count = self.debris_count.max(2);
+        let dx = body.x - primary.x;
+        let dy = body.y - primary.y;
+        let dz = body.z - primary.z;
+        let distance = (dx * dx + dy * dy + dz * dz).sqrt().max(Real::EPSILON);
+        let (nx, ny, nz) = (dx / distance, dy / distance, dz / distance);
+
+        let mass = body.mass / count as Real;
+        let radius = body_radius / count as Real; // small enough that neighbours don't touch
+        (0..count).map(|k| {
+            let offset = body_radius * (2.0 * k as Real / (count - 1) as Real - 1.0);
+            let piece = Particle {
+                x: body.x + nx * offset,
+                y: body.y + ny * offset,
+                z: body.z + nz * offset,
+                vx: body.vx,
+                vy: body.vy,
+                vz: body.vz,
+                mass,
+                ..Particle::default()
+            };
+            (piece, CelestialObject::new(radius, mass, ObjectType::Asteroid, age))
+        }).collect()
+    }
+}
+
+impl Default for TidalEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{assert_kept, totals};
+
+    // A star plunging into a black hole's Roche limit becomes a line of debris with the
+    // star's mass, momentum and centre of mass
+    #[test]
+    fn debris_keeps_mass_momentum_and_centre_of_mass() {
+        let mut engine = TidalEngine::new();
+        let mut events = EventLog::new();
+        let (hole_mass, star_mass) = (1e6, 2.0);
+        let mut particles: ParticleStore = [
+            (Particle::new(0.0, 0.0, 0.0, 0.0, hole_mass), CelestialObject::new(3.0, hole_mass, ObjectType::BlackHole, 0.0)),
+            (Particle::new(300.0, 40.0, 0.0, 0.0, star_mass), CelestialObject::new(2.0, star_mass, ObjectType::Star, 0.0)),
+        ].into_iter().collect();
+        // Started the step outside the limit and ended it inside
+        let limit = engine.roche_limit(&particles, 0, 1);
+        assert!(limit > 320.0 && limit < 500.0, "Roche limit {limit}");
+        (particles.vx[1], particles.vy[1], particles.vz[1]) = (-3000.0, 20.0, 5.0);
+        let before = totals(&particles);
+
+        assert!(engine.disrupt(&mut particles, 0.1, 0.0, &mut events));
+        assert_eq!(engine.disruptions, 1);
+        assert_eq!(particles.len(), 1 + engine.debris_count);
+        assert!(particles.objects[1..].iter().all(|obj| obj.object_type == ObjectType::Asteroid));
+        assert_kept(before, totals(&particles));
+    }
+}
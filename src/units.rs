@@ -0,0 +1,175 @@
+use crate::gravity::G;
+use crate::real::{to_f64, Real};
+
+// The simulation's unit system, and conversions to SI and astronomical units.
+//
+// Distances are in astronomical units and masses in 1/200,000 of the Sun's mass (so a
+// mass of 2e5 is one Sun). The time unit then follows from G = 6.67430e-5:
+//   T = sqrt(G_sim L³ / (G_SI M)) = 1.8350e7 s = 0.581 years   (G_SI = 6.67430e-11),
+// which makes the unit of speed 8.15 km/s and the real speed of light 36,774.
+// Radii are what objects are drawn and collide with, not their real sizes - a
+// neutron star a few AU across is far easier to see than a 10 km one.
+// Luminosities are power, energy per time unit (3.6e25 W). Stars shine 4 per solar
+// mass, which puts the Sun at 0.38 of its real luminosity.
+
+// SI and astronomical reference values
+pub const SPEED_OF_LIGHT_SI: f64 = 299_792_458.0; // m / s
+pub const SOLAR_MASS_KG: f64 = 1.98847e30;
+pub const EARTH_MASS_KG: f64 = 5.9722e24;
+pub const AU_M: f64 = 1.495978707e11;
+pub const PARSEC_M: f64 = 3.085677581491367e16;
+pub const YEAR_S: f64 = 3.15576e7;             // Julian year
+pub const SOLAR_LUMINOSITY_W: f64 = 3.828e26;   // IAU nominal
+
+// One simulation unit of each, in SI
+pub const MASS_KG: f64 = SOLAR_MASS_KG / 2e5;
+pub const LENGTH_M: f64 = AU_M;
+pub const TIME_S: f64 = 1.8350318e7;
+pub const SPEED_M_PER_S: f64 = LENGTH_M / TIME_S;
+pub const ENERGY_J: f64 = MASS_KG * SPEED_M_PER_S * SPEED_M_PER_S;
+pub const POWER_W: f64 = ENERGY_J / TIME_S;
+
+// The real speed of light in simulation units. GravityEngine::speed_of_light is turned
+// far down from this so relativistic effects show up on screen
+pub const SPEED_OF_LIGHT: Real = (SPEED_OF_LIGHT_SI / SPEED_M_PER_S) as Real;
+
+// Simulation units -> SI
+pub fn mass_to_kg(mass: Real) -> f64 {
+    to_f64(mass) * MASS_KG
+}
+
+pub fn length_to_m(length: Real) -> f64 {
+    to_f64(length) * LENGTH_M
+}
+
+pub fn time_to_s(time: f64) -> f64 {
+    time * TIME_S
+}
+
+pub fn speed_to_m_per_s(speed: Real) -> f64 {
+    to_f64(speed) * SPEED_M_PER_S
+}
+
+pub fn energy_to_joules(energy: f64) -> f64 {
+    energy * ENERGY_J
+}
+
+// Simulation units -> astronomical
+pub fn mass_to_solar(mass: Real) -> f64 {
+    mass_to_kg(mass) / SOLAR_MASS_KG
+}
+
+pub fn mass_to_earth(mass: Real) -> f64 {
+    mass_to_kg(mass) / EARTH_MASS_KG
+}
+
+pub fn length_to_km(length: Real) -> f64 {
+    length_to_m(length) / 1000.0
+}
+
+pub fn length_to_parsecs(length: Real) -> f64 {
+    length_to_m(length) / PARSEC_M
+}
+
+pub fn time_to_years(time: f64) -> f64 {
+    time_to_s(time) / YEAR_S
+}
+
+pub fn speed_to_km_per_s(speed: Real) -> f64 {
+    speed_to_m_per_s(speed) / 1000.0
+}
+
+pub fn luminosity_to_solar(luminosity: Real) -> f64 {
+    to_f64(luminosity) * POWER_W / SOLAR_LUMINOSITY_W
+}
+
+// Astronomical -> simulation units, for setting up scenes
+pub fn solar_masses(solar: f64) -> Real {
+    (solar * SOLAR_MASS_KG / MASS_KG) as Real
+}
+
+// Schwarzschild radius 2 G M / c² with the real speed of light, in simulation units
+pub fn schwarzschild_radius(mass: Real) -> Real {
+    2.0 * G * mass / (SPEED_OF_LIGHT * SPEED_OF_LIGHT)
+}
+
+// Readouts, in whichever unit keeps the number readable
+pub fn format_mass(mass: Real) -> String {
+    let solar = mass_to_solar(mass);
+    if solar >= 0.01 {
+        format!("{:.3} Msun", solar)
+    } else if mass_to_earth(mass) >= 0.01 {
+        format!("{:.3} Mearth", mass_to_earth(mass))
+    } else {
+        format!("{:.2e} kg", mass_to_kg(mass))
+    }
+}
+
+pub fn format_length(length: Real) -> String {
+    let parsecs = length_to_parsecs(length);
+    if parsecs.abs() >= 0.1 {
+        format!("{:.2} pc", parsecs)
+    } else if length.abs() >= 0.01 {
+        format!("{:.2} AU", length)
+    } else {
+        format!("{:.0} km", length_to_km(length))
+    }
+}
+
+pub fn format_time(time: f64) -> String {
+    let years = time_to_years(time);
+    if years.abs() >= 1e6 {
+        format!("{:.2} Myr", years / 1e6)
+    } else if years.abs() >= 1e3 {
+        format!("{:.2} kyr", years / 1e3)
+    } else if years.abs() >= 1.0 {
+        format!("{:.1} yr", years)
+    } else {
+        format!("{:.1} days", years * 365.25)
+    }
+}
+
+pub fn format_speed(speed: Real) -> String {
+    format!("{:.1} km/s", speed_to_km_per_s(speed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() <= tolerance * b.abs()
+    }
+
+    // G in simulation units, taken back to SI with the unit conversions, is the real G
+    #[test]
+    fn units_reproduce_the_real_gravitational_constant() {
+        let g_si = to_f64(G) * LENGTH_M.powi(3) / (MASS_KG * TIME_S * TIME_S);
+        assert!(close(g_si, 6.67430e-11, 1e-6), "G = {g_si:e} m³ / kg s²");
+    }
+
+    #[test]
+    fn derived_constants() {
+        assert!(close(to_f64(SPEED_OF_LIGHT), 36774.0, 1e-4), "c = {}", SPEED_OF_LIGHT);
+        assert!(close(SPEED_M_PER_S, 8152.4, 1e-4), "speed unit {SPEED_M_PER_S} m/s");
+        assert!(close(time_to_years(1.0), 0.5815, 1e-3), "time unit {} years", time_to_years(1.0));
+        assert!(close(mass_to_solar(2e5), 1.0, 1e-6));
+        // A solar mass black hole is about 3 km across, radius-wise
+        assert!(close(length_to_km(schwarzschild_radius(solar_masses(1.0))), 2.953, 1e-3));
+    }
+
+    #[test]
+    fn readouts_pick_a_readable_unit() {
+        assert_eq!(format_mass(solar_masses(1.5)), "1.500 Msun");
+        assert_eq!(format_mass(solar_masses(3.0e-6)), "0.999 Mearth");
+        assert_eq!(format_mass(1e-10), "9.94e14 kg");
+        assert_eq!(format_length(1.5), "1.50 AU");
+        assert_eq!(format_length(206_265.0), "1.00 pc");
+        assert_eq!(format_length(0.001), "149598 km");
+        assert_eq!(format_time(1.0), "212.4 days");
+        assert_eq!(format_time(10.0), "5.8 yr");
+        assert_eq!(format_time(1e4), "5.81 kyr");
+        assert_eq!(format_time(1e7), "5.81 Myr");
+        assert_eq!(format_speed(1.0), "8.2 km/s");
+    }
+}
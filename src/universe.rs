@@ -1,19 +1,120 @@
 use std::time::Instant;
 use rand::Rng;
 use crate::objects::{CelestialObject, ObjectType};
-use crate::physics::PhysicsEngine;
+use crate::particles::{Body, Particle, ParticleStore};
+use crate::physics::{PhysicsEngine, VelocityPolicy};
 use crate::expansion::ExpansionEngine;
+use crate::collisions::{CollisionEngine, CollisionMode};
+use crate::accretion::AccretionEngine;
+use crate::tidal::TidalEngine;
+use crate::events::{EventKind, EventLog, SimulationEvent};
+use crate::diagnostics::Diagnostics;
+use crate::gravitational_waves::GravitationalWaves;
 use crate::gravity::G;
+use crate::halo::{DarkMatterHalo, HaloProfile};
+use crate::radiation::{RadiationEngine, RadiationMode, RadiationPressure};
+use crate::units;
+use crate::real::{consts, to_f64, Real};
+
+// What happens at the edge of the simulated region
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BoundaryCondition {
+    Open,     // objects that wander too far are removed
+    Periodic, // leaving one side of the box brings you back on the other
+}
+
+impl BoundaryCondition {
+    pub fn name(&self) -> &'static str {
+        match self {
+            BoundaryCondition::Open => "Open",
+            BoundaryCondition::Periodic => "Periodic",
+        }
+    }
+    
+    pub fn toggled(&self) -> Self {
+        match self {
+            BoundaryCondition::Open => BoundaryCondition::Periodic,
+            BoundaryCondition::Periodic => BoundaryCondition::Open,
+        }
+    }
+}
 
 pub struct Universe {
     pub width: f32,
     pub height: f32,
-    pub objects: Vec<CelestialObject>,
+    pub particles: ParticleStore,
     pub physics_engine: PhysicsEngine,
     pub expansion_engine: ExpansionEngine,
+    pub collision_engine: CollisionEngine,
+    pub accretion_engine: AccretionEngine,
+    pub tidal_engine: TidalEngine,
+    pub radiation_engine: RadiationEngine,
+    pub gravitational_waves: GravitationalWaves,
+    pub events: EventLog,
+    pub diagnostics: Diagnostics,
     pub start_time: Instant,
-    pub age: f64,
+    pub age: f64, // simulated time since the start, in simulation units (see units.rs)
     pub time_scale: f64,
+    pub boundary: BoundaryCondition,
+    // Scenes get depth: a galactic disk with thickness, solar systems on inclined orbits
+    // and asteroids and comets off the plane. Otherwise everything stays in the z = 0 plane
+    pub three_d: bool,
+    // Dark matter halo the default scene's galaxy sits in (None: the galaxy has no halo)
+    pub halo_profile: HaloProfile,
+    // Radiation reaction and speed of light from before the black hole demo changed them,
+    // put back when the next scene is built
+    saved_gravity: Option<(bool, Real)>,
+}
+
+// A rotation that tips the x-y plane over by `inclination` about the line at angle `node`
+// in it, for laying out inclined orbits in 3D scenes: an orbit set up flat, then tilted
+#[derive(Debug, Clone, Copy)]
+struct Tilt {
+    inclination: Real,
+    node: Real,
+}
+
+impl Tilt {
+    const FLAT: Tilt = Tilt { inclination: 0.0, node: 0.0 };
+
+    // Random orientation, at most `max_inclination` radians off flat
+    fn random(rng: &mut impl Rng, max_inclination: Real) -> Self {
+        Tilt {
+            inclination: rng.gen_range(-max_inclination..=max_inclination),
+            node: rng.gen_range(0.0..2.0 * consts::PI),
+        }
+    }
+
+    // Flat (x, y) -> tilted (x, y, z). The line of nodes is the x axis turned by `node`
+    fn apply(&self, x: Real, y: Real) -> (Real, Real, Real) {
+        if self.inclination == 0.0 {
+            return (x, y, 0.0);
+        }
+        let (sin_node, cos_node) = self.node.sin_cos();
+        let (sin_tilt, cos_tilt) = self.inclination.sin_cos();
+        // Along the line of nodes, and across it in the plane
+        let along = x * cos_node + y * sin_node;
+        let across = -x * sin_node + y * cos_node;
+        (along * cos_node - across * cos_tilt * sin_node,
+         along * sin_node + across * cos_tilt * cos_node,
+         across * sin_tilt)
+    }
+}
+
+// An object at (x, y, z) moving with (vx, vy, vz)
+fn place(position: (Real, Real, Real), velocity: (Real, Real, Real), radius: Real, mass: Real,
+         object_type: ObjectType, age: f64) -> Body {
+    let particle = Particle {
+        x: position.0,
+        y: position.1,
+        z: position.2,
+        vx: velocity.0,
+        vy: velocity.1,
+        vz: velocity.2,
+        mass,
+        ..Particle::default()
+    };
+    (particle, CelestialObject::new(radius, mass, object_type, age))
 }
 
 impl Universe {
@@ -21,22 +122,85 @@ impl Universe {
         let mut universe = Universe {
             width,
             height,
-            objects: Vec::new(),
+            particles: ParticleStore::new(),
             physics_engine: PhysicsEngine::new(),
             expansion_engine: ExpansionEngine::new(),
+            collision_engine: CollisionEngine::new(),
+            accretion_engine: AccretionEngine::new(),
+            tidal_engine: TidalEngine::new(),
+            radiation_engine: RadiationEngine::new(),
+            gravitational_waves: GravitationalWaves::new(),
+            events: EventLog::new(),
+            diagnostics: Diagnostics::new(),
             start_time: Instant::now(),
             age: 0.0,
             time_scale: 0.1,
+            boundary: BoundaryCondition::Open,
+            three_d: false,
+            halo_profile: HaloProfile::Nfw,
+            saved_gravity: None,
         };
+        universe.sync_radiation();
         
         universe.create_stable_universe();
         universe.add_random_kick(); 
+        universe.restart_diagnostics();
         universe
     }
     
+    // Switch between the flat and the 3D universe, starting the default scene over
+    pub fn set_three_d(&mut self, three_d: bool) {
+        self.three_d = three_d;
+        self.physics_engine.gravity_engine.fit_dimension(three_d);
+        self.rebuild();
+    }
+    
+    // Put the galaxy in a different kind of dark matter halo. The default scene is rebuilt
+    // around it, so orbits start out circular in the new potential
+    pub fn set_halo_profile(&mut self, profile: HaloProfile) {
+        self.halo_profile = profile;
+        self.rebuild();
+    }
+    
+    // Change what starlight does. The default scene is rebuilt, with its dust rings (only
+    // there while the light is on) on orbits that are circular under the reduced pull
+    pub fn set_radiation_mode(&mut self, mode: RadiationMode) {
+        self.radiation_engine.mode = mode;
+        self.rebuild();
+    }
+    
+    // Register starlight as a force field for the current mode and speed of light, in
+    // place of whatever was registered before
+    fn sync_radiation(&mut self) {
+        self.physics_engine.remove_force_field(RadiationPressure::NAME);
+        if let Some(field) = self.radiation_engine.field(self.physics_engine.gravity_engine.speed_of_light) {
+            self.physics_engine.add_force_field(field);
+        }
+    }
+    
+    // Undo the black hole demo's radiation reaction and speed of light, if it was the
+    // last scene
+    fn restore_gravity(&mut self) {
+        if let Some((radiation_reaction, speed_of_light)) = self.saved_gravity.take() {
+            let gravity = &mut self.physics_engine.gravity_engine;
+            gravity.radiation_reaction = radiation_reaction;
+            gravity.speed_of_light = speed_of_light;
+            self.sync_radiation();
+        }
+    }
+    
+    // Start the default scene over
+    fn rebuild(&mut self) {
+        self.restore_gravity();
+        self.sync_radiation();
+        self.particles.clear();
+        self.physics_engine.invalidate_forces();
+        self.create_stable_universe();
+        self.add_random_kick();
+        self.restart_diagnostics();
+    }
+    
     fn create_stable_universe(&mut self) {
-        let mut rng = rand::thread_rng();
-        
         self.create_central_galaxy();
         
         self.create_stable_solar_systems();
@@ -47,59 +211,95 @@ impl Universe {
     fn create_central_galaxy(&mut self) {
         let mut rng = rand::thread_rng();
         
-        let central_mass = 5e7;
-        self.objects.push(CelestialObject::new(
-            0.0, 0.0, 0.0, 0.0, 15.0, central_mass, ObjectType::GalaxyCenter, self.age
-        ));
+        // 250 Suns of bulge around a 2,500 solar mass black hole
+        let central_mass = units::solar_masses(250.0);
+        self.particles.push(
+            Particle::new(0.0, 0.0, 0.0, 0.0, central_mass),
+            CelestialObject::new(15.0, central_mass, ObjectType::GalaxyCenter, self.age),
+        );
         // self.objects.push(CelestialObject::new(
         //     0.0, 0.0, 0.0, 0.0, 3.0, central_mass * 1, ObjectType::BlackHole, self.age
         // ));
         
         // Central black hole
-        self.objects.push(CelestialObject::new(
-            0.0, 0.0, 0.0, 0.0, 3.0, central_mass * 10.0, ObjectType::BlackHole, self.age
-        ));
+        self.particles.push(
+            Particle::new(0.0, 0.0, 0.0, 0.0, central_mass * 10.0),
+            CelestialObject::new(3.0, central_mass * 10.0, ObjectType::BlackHole, self.age),
+        );
         
-        // Stars in stable orbits around the center
+        // Dark matter halo around it, with as much mass inside 250 units as the bulge and
+        // the black hole together. It outweighs them further out, which keeps the
+        // rotation curve from falling off
+        let core_mass = self.galactic_core_mass();
+        self.physics_engine.gravity_engine.halos.clear();
+        if self.halo_profile != HaloProfile::None {
+            self.physics_engine.gravity_engine.halos.push(
+                DarkMatterHalo::new(self.halo_profile, (0.0, 0.0, 0.0), core_mass, 250.0)
+            );
+        }
+        
+        // Stars in stable orbits around the center. In 3D each orbit is tipped a little
+        // out of the plane, which gives the disk its thickness
         for i in 0..30 {
-            let distance = 100.0 + i as f32 * 25.0;
-            let angle = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
+            let distance = 100.0 + i as Real * 25.0;
+            let angle = rng.gen_range(0.0..2.0 * consts::PI);
             
-            // Orbital speed for circular orbit (Keplers law)
-            let orbital_speed = (G * central_mass / distance).sqrt() * 0.7;
+            // Orbital speed for circular orbit (Keplers law) - the black hole and the halo pull too
+            let orbital_speed = self.galactic_speed(core_mass, distance);
             
-            self.objects.push(CelestialObject::new(
-                distance * angle.cos(),
-                distance * angle.sin(),
-                -orbital_speed * angle.sin(),// Tangent to orbit
-                // -orbital_speed * angle.cos(),  
-                orbital_speed * angle.cos(),
+            let tilt = if self.three_d { Tilt::random(&mut rng, 0.15) } else { Tilt::FLAT };
+            let (star, object) = place(
+                tilt.apply(distance * angle.cos(), distance * angle.sin()),
+                // Tangent to orbit
+                tilt.apply(-orbital_speed * angle.sin(), orbital_speed * angle.cos()),
                 rng.gen_range(1.0..2.5),
-                rng.gen_range(5e4..2e5),
+                units::solar_masses(rng.gen_range(0.25..1.0)),
                 ObjectType::Star,
                 self.age,
-            ));
+            );
+            self.particles.push(star, object);
         }
     }
     
     fn create_stable_solar_systems(&mut self) {
         let mut rng = rand::thread_rng();
         
+        let core_mass = self.galactic_core_mass();
+        
         // Create a few solar systems
-        for system_num in 0..4 {
-            let system_x = rng.gen_range(-300.0..300.0);
-            let system_y = rng.gen_range(-300.0..300.0);
+        for _ in 0..4 {
+            // Far enough out that the galactic core doesn't strip the planets away
+            let system_distance = rng.gen_range(200.0..400.0);
+            let system_angle = rng.gen_range(0.0..2.0 * consts::PI);
+            
+            // The whole system orbits the galactic center. In 3D it sits a little off the
+            // galactic plane, and its planets orbit in a plane of their own
+            let (galactic_tilt, system_tilt) = if self.three_d {
+                (Tilt::random(&mut rng, 0.15), Tilt::random(&mut rng, 1.2))
+            } else {
+                (Tilt::FLAT, Tilt::FLAT)
+            };
+            let system_speed = self.galactic_speed(core_mass, system_distance);
+            let system = galactic_tilt.apply(system_distance * system_angle.cos(), system_distance * system_angle.sin());
+            let system_velocity = galactic_tilt.apply(-system_speed * system_angle.sin(), system_speed * system_angle.cos());
+            // Something on an orbit set up flat around the star, moved into the system's plane
+            let around = |x: Real, y: Real, vx: Real, vy: Real| {
+                let (dx, dy, dz) = system_tilt.apply(x, y);
+                let (dvx, dvy, dvz) = system_tilt.apply(vx, vy);
+                ((system.0 + dx, system.1 + dy, system.2 + dz),
+                 (system_velocity.0 + dvx, system_velocity.1 + dvy, system_velocity.2 + dvz))
+            };
             
             // Central star
-            let star_mass = rng.gen_range(1e6..5e6);
+            let star_mass = units::solar_masses(rng.gen_range(5.0..25.0));
             let star_type = if rng.gen_bool(0.1) {
                 ObjectType::WhiteDwarf
             } else {
                 ObjectType::Star
             };
             
-            self.objects.push(CelestialObject::new(
-                system_x, system_y, 0.0, 0.0, 
+            let (star, star_object) = place(
+                system, system_velocity,
                 match star_type {
                     ObjectType::WhiteDwarf => rng.gen_range(1.0..1.5),
                     _ => rng.gen_range(2.0..4.0),
@@ -107,26 +307,34 @@ impl Universe {
                 star_mass,
                 star_type,
                 self.age,
-            ));
+            );
+            let light_to_mass = star_object.luminosity / star.mass;
+            self.particles.push(star, star_object);
             
             // Planets with stable orbits
             for planet_idx in 0..rng.gen_range(2..4) {
-                let orbit_radius = 25.0 + planet_idx as f32 * 15.0;
-                let angle = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
+                let orbit_radius = 25.0 + planet_idx as Real * 15.0;
+                let angle = rng.gen_range(0.0..2.0 * consts::PI);
                 
                 // Circular orbit speed
-                let orbital_speed = (G * star_mass / orbit_radius).sqrt();
+                let orbital_speed = self.physics_engine.gravity_engine.circular_speed(star_mass, orbit_radius);
                 
-                self.objects.push(CelestialObject::new(
-                    system_x + orbit_radius * angle.cos(),
-                    system_y + orbit_radius * angle.sin(),
+                let (position, velocity) = around(
+                    orbit_radius * angle.cos(),
+                    orbit_radius * angle.sin(),
                     -orbital_speed * angle.sin(),
                     orbital_speed * angle.cos(),
+                );
+                let (body, object) = place(
+                    position,
+                    velocity,
                     rng.gen_range(0.5..1.2),
-                    rng.gen_range(1e3..5e3),
+                    // 5 to 25 Jupiter masses
+                    units::solar_masses(rng.gen_range(0.005..0.025)),
                     ObjectType::Planet,
                     self.age,
-                ));
+                );
+                self.particles.push(body, object);
             }
             
             // Add a neutron star or pulsar to some systems
@@ -138,25 +346,56 @@ impl Universe {
                 };
                 
                 let exotic_distance = 50.0;
-                let exotic_angle = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
-                let exotic_speed = (G * star_mass / exotic_distance).sqrt() * 0.8;
+                let exotic_angle = rng.gen_range(0.0..2.0 * consts::PI);
+                let exotic_speed = self.physics_engine.gravity_engine.circular_speed(star_mass, exotic_distance) * 0.8;
                 
-                self.objects.push(CelestialObject::new(
-                    system_x + exotic_distance * exotic_angle.cos(),
-                    system_y + exotic_distance * exotic_angle.sin(),
+                let (position, velocity) = around(
+                    exotic_distance * exotic_angle.cos(),
+                    exotic_distance * exotic_angle.sin(),
                     -exotic_speed * exotic_angle.sin(),
                     exotic_speed * exotic_angle.cos(),
+                );
+                let (body, object) = place(
+                    position,
+                    velocity,
                     rng.gen_range(0.3..0.6),
-                    rng.gen_range(5e4..1e5),
+                    units::solar_masses(rng.gen_range(0.25..0.5)),
                     exotic_type,
                     self.age,
-                ));
+                );
+                self.particles.push(body, object);
+            }
+            
+            // With radiation on, a ring of dust outside the planets. Starlight pushes each
+            // grain out with β of the star's unsoftened pull, so they orbit a little slower
+            // than the planets would at the same distance
+            let Some(light) = self.radiation_engine.field(self.physics_engine.gravity_engine.speed_of_light) else {
+                continue;
+            };
+            for _ in 0..16 {
+                let orbit_radius = rng.gen_range(65.0..80.0);
+                let angle = rng.gen_range(0.0..2.0 * consts::PI);
+                let beta = rng.gen_range(0.05..0.4);
+                let gravity_speed = self.physics_engine.gravity_engine.circular_speed(star_mass, orbit_radius);
+                let push = beta * G * star_mass / orbit_radius;
+                let orbital_speed = (gravity_speed * gravity_speed - push).sqrt();
+                
+                let (position, velocity) = around(
+                    orbit_radius * angle.cos(),
+                    orbit_radius * angle.sin(),
+                    -orbital_speed * angle.sin(),
+                    orbital_speed * angle.cos(),
+                );
+                let (grain, object) = light.dust_grain(position, velocity, beta, light_to_mass, self.age);
+                self.particles.push(grain, object);
             }
         }
     }
     
     fn create_small_objects(&mut self) {
         let mut rng = rand::thread_rng();
+        // How far off the plane loose objects wander in 3D
+        let depth: Real = if self.three_d { 100.0 } else { 0.0 };
         
         // Asteroids
         for _ in 0..20 {
@@ -165,17 +404,15 @@ impl Universe {
             
             // Random initial velocity
             let speed = rng.gen_range(0.05..0.2);
-            let angle = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
+            let angle = rng.gen_range(0.0..2.0 * consts::PI);
             //----------------------------
-            self.objects.push(CelestialObject::new(
-                x, y,
-                speed * angle.cos(),
-                speed * angle.sin(),
-                rng.gen_range(0.1..0.3),
-                rng.gen_range(0.5..5.0),
-                ObjectType::Asteroid,
-                self.age,
-            ));
+            let radius = rng.gen_range(0.1..0.3);
+            let mut asteroid = Particle::new(x, y, speed * angle.cos(), speed * angle.sin(), rng.gen_range(0.5..5.0));
+            if self.three_d {
+                asteroid.z = rng.gen_range(-depth..depth);
+                asteroid.vz = rng.gen_range(-speed..speed);
+            }
+            self.particles.push(asteroid, CelestialObject::new(radius, asteroid.mass, ObjectType::Asteroid, self.age));
                         //----------------------------
 
         }
@@ -186,88 +423,272 @@ impl Universe {
             let y = rng.gen_range(-500.0..500.0);
             
             let speed = rng.gen_range(0.1..0.3);
-            let angle = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
+            let angle = rng.gen_range(0.0..2.0 * consts::PI);
             
-            self.objects.push(CelestialObject::new(
-                x, y,
-                speed * angle.cos(),
-                speed * angle.sin(),
-                rng.gen_range(0.2..0.5),
-                rng.gen_range(1.0..3.0),
-                ObjectType::Comet,
-                self.age,
-            ));
+            let radius = rng.gen_range(0.2..0.5);
+            let mut comet = Particle::new(x, y, speed * angle.cos(), speed * angle.sin(), rng.gen_range(1.0..3.0));
+            if self.three_d {
+                comet.z = rng.gen_range(-depth..depth);
+                comet.vz = rng.gen_range(-speed..speed);
+            }
+            self.particles.push(comet, CelestialObject::new(radius, comet.mass, ObjectType::Comet, self.age));
+        }
+    }
+    
+    // Replace everything with a giant impact: a Mars-sized body hitting a young Earth at a
+    // glancing angle, as in the Moon formation theory. Needs collisions (and fragmentation)
+    // on to do anything interesting, so those get switched on too
+    pub fn create_giant_impact(&mut self) {
+        self.restore_gravity();
+        self.particles.clear();
+        self.physics_engine.gravity_engine.halos.clear();
+        self.physics_engine.invalidate_forces();
+        if self.collision_engine.mode == CollisionMode::Off {
+            self.collision_engine.mode = CollisionMode::Merge;
         }
+        self.collision_engine.fragmentation = true;
         
-        // Dark matter
-        for _ in 0..15 {
-            let x = rng.gen_range(-600.0..600.0);
-            let y = rng.gen_range(-600.0..600.0);
-            
-            self.objects.push(CelestialObject::new(
-                x, y, 0.0, 0.0,
-                rng.gen_range(1.0..2.0),
-                rng.gen_range(1e4..5e4),
-                ObjectType::DarkMatter,
-                self.age,
-            ));
+        // Scaled far up from the real pair (Earth is 3e-6 Suns) so the impact plays out
+        // in seconds on screen; only the mass ratio and the geometry matter here
+        let earth_mass = units::solar_masses(2.5);
+        let earth_radius = 8.0;
+        
+        // Theia: a tenth of the mass, coming in from the right and aimed about 45 degrees
+        // off centre at 1.3 times the pair's escape speed - hard enough to be past the
+        // erosion threshold, so it throws off a debris disk instead of just merging
+        let theia_mass = earth_mass * 0.1;
+        let theia_radius = earth_radius * Real::cbrt(0.1);
+        let impact_offset = (earth_radius + theia_radius) * 0.7;
+        let pair_radius = (earth_radius.powi(3) + theia_radius.powi(3)).cbrt();
+        let approach_speed = (2.0 * G * (earth_mass + theia_mass) / pair_radius).sqrt() * 1.3;
+        
+        // Earth recoils so the pair's centre of mass stays put
+        self.particles.push(
+            Particle::new(0.0, 0.0, approach_speed * theia_mass / earth_mass, 0.0, earth_mass),
+            CelestialObject::new(earth_radius, earth_mass, ObjectType::Planet, self.age),
+        );
+        self.particles.push(
+            Particle::new(60.0, impact_offset, -approach_speed, 0.0, theia_mass),
+            CelestialObject::new(theia_radius, theia_mass, ObjectType::Planet, self.age),
+        );
+        self.restart_diagnostics();
+    }
+    
+    // Replace everything with two black holes on a close circular orbit, with radiation
+    // reaction on and the speed of light turned down far enough that they spiral
+    // together and merge within a few minutes
+    pub fn create_binary_black_hole(&mut self) {
+        self.particles.clear();
+        self.physics_engine.gravity_engine.halos.clear();
+        self.physics_engine.invalidate_forces();
+        
+        let gravity = &mut self.physics_engine.gravity_engine;
+        self.saved_gravity.get_or_insert((gravity.radiation_reaction, gravity.speed_of_light));
+        gravity.radiation_reaction = true;
+        gravity.speed_of_light = 120.0;
+        self.sync_radiation();
+        
+        let masses: [Real; 2] = [units::solar_masses(2500.0), units::solar_masses(1500.0)];
+        let separation = 40.0;
+        let total = masses[0] + masses[1];
+        let speed = self.physics_engine.gravity_engine.circular_speed(total, separation);
+        
+        // Each hole circles the common centre of mass
+        for (k, &mass) in masses.iter().enumerate() {
+            let other = masses[1 - k];
+            let side = if k == 0 { -1.0 } else { 1.0 };
+            self.particles.push(
+                Particle::new(side * separation * other / total, 0.0, 0.0, side * speed * other / total, mass),
+                CelestialObject::new(3.0, mass, ObjectType::BlackHole, self.age),
+            );
         }
+        self.restart_diagnostics();
+    }
+    
+    // Make the current state the t = 0 the conservation diagnostics compare against
+    pub fn restart_diagnostics(&mut self) {
+        self.diagnostics.reset(&self.particles, &mut self.physics_engine);
+    }
+    
+    // Circular orbit speed around the galactic center, from the core and the halo together
+    fn galactic_speed(&self, core_mass: Real, distance: Real) -> Real {
+        let gravity = &self.physics_engine.gravity_engine;
+        let core = gravity.circular_speed(core_mass, distance);
+        let halo: Real = gravity.halos.iter().map(|halo| halo.circular_speed(distance).powi(2)).sum();
+        (core * core + halo).sqrt()
+    }
+    
+    // Mass sitting at the galactic center (galaxy core + central black hole)
+    fn galactic_core_mass(&self) -> Real {
+        self.particles.iter()
+            .filter(|(_, obj)| matches!(obj.object_type, ObjectType::GalaxyCenter | ObjectType::BlackHole))
+            .filter(|(p, _)| p.x * p.x + p.y * p.y + p.z * p.z < 1.0)
+            .map(|(p, _)| p.mass)
+            .sum()
     }
     
     fn add_random_kick(&mut self) {
         let mut rng = rand::thread_rng();
         
-        for obj in self.objects.iter_mut() {
-            // Only give small/medium objects a little kick
-            if obj.mass < 1e5 && !matches!(obj.object_type, ObjectType::GalaxyCenter) {
+        // Only bodies under half a solar mass get a little kick: planets, asteroids, comets, the
+        // exotic companions and the lighter half of the disk stars. The stars that hold
+        // planetary systems (5 to 25 solar masses) and the black holes keep their orbits
+        let kicked_below = units::solar_masses(0.5);
+        let particles = &mut self.particles;
+        for i in 0..particles.len() {
+            if particles.mass[i] < kicked_below && !matches!(particles.objects[i].object_type, ObjectType::GalaxyCenter) {
                 let kick_strength = 0.05;
-                obj.vx += rng.gen_range(-kick_strength..kick_strength);
-                obj.vy += rng.gen_range(-kick_strength..kick_strength);
+                particles.vx[i] += rng.gen_range(-kick_strength..kick_strength);
+                particles.vy[i] += rng.gen_range(-kick_strength..kick_strength);
+                if self.three_d {
+                    particles.vz[i] += rng.gen_range(-kick_strength..kick_strength);
+                }
             }
         }
     }
     
     pub fn update(&mut self, expansion_speed: f32) {
-        self.age += 0.001 * self.time_scale;
+        let dt = self.physics_engine.time_step(self.time_scale as Real);
+        self.age += to_f64(dt);
         
-        // 1. Apply gravity
-        self.physics_engine.update_physics(&mut self.objects, self.time_scale as f32);
+        // 1. Apply gravity and move everything with the selected integrator
+        self.physics_engine.update_physics(&mut self.particles, self.time_scale as Real);
+        self.log_speed_clamp();
         
-        self.expansion_engine.apply_expansion(&mut self.objects, expansion_speed * 0.01, self.age as f32);
+        // Comets close to a star shed dust, which the light blows back into a tail
+        if self.radiation_engine.shed_comet_dust(&mut self.particles, self.physics_engine.gravity_engine.speed_of_light,
+                                                 dt, self.age) {
+            self.physics_engine.invalidate_forces();
+        }
         
-        // 3. Update positions
-        for obj in &mut self.objects {
-            obj.update(self.time_scale as f32);
+        // 2. Compact binaries that spiralled in merge, stars and planets that dived too
+        // close to something heavy are torn apart, and black holes swallow whatever fell in
+        if self.gravitational_waves.merge_binaries(&mut self.particles, &self.physics_engine.gravity_engine,
+                                                   dt, self.age, &mut self.events) {
+            self.physics_engine.invalidate_forces();
+        }
+        if self.tidal_engine.disrupt(&mut self.particles, dt, self.age, &mut self.events) {
+            self.physics_engine.invalidate_forces();
+        }
+        if self.accretion_engine.absorb(&mut self.particles, dt, self.age, &mut self.events) {
+            self.physics_engine.invalidate_forces();
         }
         
-        // 4. Remove objects that wandered too far (optional)
-        self.remove_distant_objects();
+        // 3. Bodies that touched during the step stick together
+        if self.collision_engine.resolve(&mut self.particles, dt, self.age) {
+            self.physics_engine.invalidate_forces();
+        }
+        
+        // 4. Stretch space a little. The cached forces were for the unstretched positions
+        if self.expansion_engine.apply_expansion(&mut self.particles, expansion_speed as Real * 0.01, self.age as Real) {
+            self.physics_engine.invalidate_forces();
+        }
+        
+        // 5. Remove objects that wandered too far, or wrap them around the box
+        // Either way the cached forces belong to the old positions or the old list
+        let changed = match self.boundary {
+            BoundaryCondition::Open => self.remove_distant_objects(),
+            BoundaryCondition::Periodic => self.wrap_positions(),
+        };
+        if changed {
+            self.physics_engine.invalidate_forces();
+        }
+        
+        // 6. Check how well energy, momentum and angular momentum held up
+        self.diagnostics.record(&self.particles, &mut self.physics_engine, dt);
     }
     
-    fn remove_distant_objects(&mut self) {
-        let max_distance = 2000.0;
-        self.objects.retain(|obj| {
-            let distance = (obj.x * obj.x + obj.y * obj.y).sqrt();
-            distance < max_distance
+    // Say when the velocity clamp kicked in. It can fire every step for a while, so at most
+    // once per unit of time to keep the log readable; the diagnostics count every one
+    fn log_speed_clamp(&mut self) {
+        if self.physics_engine.velocity_policy != VelocityPolicy::Clamp {
+            return;
+        }
+        let Some((index, speed)) = self.physics_engine.fastest_limited else {
+            return;
+        };
+        if self.events.since(self.age, 1.0).any(|event| matches!(event.kind, EventKind::SpeedClamped { .. })) {
+            return;
+        }
+        let particles = &self.particles;
+        self.events.push(SimulationEvent {
+            age: self.age,
+            x: particles.x[index],
+            y: particles.y[index],
+            z: particles.z[index],
+            kind: EventKind::SpeedClamped {
+                object: particles.objects[index].object_type,
+                speed,
+                limit: self.physics_engine.max_speed,
+                count: self.physics_engine.speed_limited,
+            },
         });
     }
     
-    pub fn get_object_at_position(&self, x: f32, y: f32, zoom: f32) -> Option<(usize, &CelestialObject)> {
-        let search_radius = 25.0 / zoom.max(0.1);
-        
-        for (i, obj) in self.objects.iter().enumerate() {
-            let dx = obj.x - x;
-            let dy = obj.y - y;
-            let distance = (dx * dx + dy * dy).sqrt();
-            
-            if distance < obj.radius + search_radius {
-                return Some((i, obj));
+    // Periodic box - the same one the particle mesh solver uses (a cube, in 3D).
+    // Returns true if anything crossed an edge
+    fn wrap_positions(&mut self) -> bool {
+        let box_size = self.physics_engine.gravity_engine.mesh.box_size;
+        let half_box = box_size / 2.0;
+        let wrap = |coordinate: &mut Real| {
+            if *coordinate < -half_box || *coordinate >= half_box {
+                *coordinate = (*coordinate + half_box).rem_euclid(box_size) - half_box;
+                true
+            } else {
+                false
             }
+        };
+        let mut wrapped = false;
+        let particles = &mut self.particles;
+        for i in 0..particles.len() {
+            // Not ||: every coordinate has to come back into the box
+            wrapped |= wrap(&mut particles.x[i]) | wrap(&mut particles.y[i]) | wrap(&mut particles.z[i]);
         }
-        None
+        wrapped
+    }
+    
+    // Returns true if anything was removed
+    fn remove_distant_objects(&mut self) -> bool {
+        let max_distance = 2000.0;
+        let before = self.particles.len();
+        let (x, y, z) = (&self.particles.x, &self.particles.y, &self.particles.z);
+        let keep: Vec<bool> = (0..before)
+            .map(|i| (x[i] * x[i] + y[i] * y[i] + z[i] * z[i]).sqrt() < max_distance)
+            .collect();
+        self.particles.retain(|i| keep[i]);
+        self.particles.len() != before
     }
     
-    pub fn get_object_by_index(&self, index: usize) -> Option<&CelestialObject> {
-        self.objects.get(index)
+    // A body's state and its metadata
+    pub fn get_object_by_index(&self, index: usize) -> Option<(Particle, &CelestialObject)> {
+        let object = self.particles.objects.get(index)?;
+        Some((self.particles.particle(index), object))
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A body that left the periodic box through one edge comes back in through the opposite
+    // one, at the same distance past it and with the same velocity. One inside stays put
+    #[test]
+    fn wrapping_brings_bodies_back_through_the_opposite_edge() {
+        let mut universe = Universe::new(800.0, 600.0);
+        universe.particles.clear();
+        let half_box = universe.physics_engine.gravity_engine.mesh.box_size / 2.0;
+        universe.particles.extend([
+            (Particle::new(half_box + 3.0, 10.0, 4.0, 0.0, 1.0), CelestialObject::new(1.0, 1.0, ObjectType::Asteroid, 0.0)),
+            (Particle::new(-20.0, -half_box - 2.0, 0.0, -5.0, 1.0), CelestialObject::new(1.0, 1.0, ObjectType::Asteroid, 0.0)),
+            (Particle::new(5.0, 5.0, 1.0, 1.0, 1.0), CelestialObject::new(1.0, 1.0, ObjectType::Asteroid, 0.0)),
+        ]);
+        universe.particles.z[2] = half_box + 1.0;
+
+        assert!(universe.wrap_positions());
+        let p = &universe.particles;
+        assert_eq!((p.x[0], p.y[0], p.vx[0]), (-half_box + 3.0, 10.0, 4.0));
+        assert_eq!((p.x[1], p.y[1], p.vy[1]), (-20.0, half_box - 2.0, -5.0));
+        assert_eq!((p.x[2], p.y[2], p.z[2]), (5.0, 5.0, -half_box + 1.0));
+        assert!(!universe.wrap_positions(), "wrapped a body that was already inside");
+    }
+}
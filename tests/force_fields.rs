@@ -0,0 +1,74 @@
+// Registering extra forces on a headless PhysicsEngine through the library's public API
+use bigbang_simulator::force_field::{ClosureField, UniformField};
+use bigbang_simulator::objects::{CelestialObject, ObjectType};
+use bigbang_simulator::particles::{Particle, ParticleStore};
+use bigbang_simulator::real::Real;
+use bigbang_simulator::{ForceField, PhysicsEngine};
+
+// Pulls everything back towards the origin like a spring: a = -k r
+struct Spring {
+    stiffness: Real,
+}
+
+impl ForceField for Spring {
+    fn name(&self) -> &str {
+        "Spring"
+    }
+
+    fn acceleration_on(&self, i: usize, particles: &ParticleStore) -> (Real, Real, Real) {
+        (-self.stiffness * particles.x[i], -self.stiffness * particles.y[i], -self.stiffness * particles.z[i])
+    }
+}
+
+// One object alone, so gravity adds nothing and only the registered fields move it
+fn lone_planet(x: Real, y: Real) -> ParticleStore {
+    let mut particles = ParticleStore::new();
+    particles.push(Particle::new(x, y, 0.0, 0.0, 1.0), CelestialObject::new(1.0, 1.0, ObjectType::Planet, 0.0));
+    particles
+}
+
+#[test]
+fn uniform_field_kicks_every_step() {
+    let mut engine = PhysicsEngine::new();
+    engine.add_force_field(UniformField { acceleration: (0.5, -0.25, 0.0) });
+    let mut particles = lone_planet(0.0, 0.0);
+    let dt = engine.time_step(1.0);
+
+    engine.update_physics(&mut particles, 1.0);
+
+    // Leapfrog is exact for a constant force
+    assert!((particles.vx[0] - 0.5 * dt).abs() < 1e-5);
+    assert!((particles.vy[0] + 0.25 * dt).abs() < 1e-5);
+    assert!((particles.x[0] - 0.25 * dt * dt).abs() < 1e-5);
+}
+
+#[test]
+fn custom_and_closure_fields_add_up() {
+    let mut engine = PhysicsEngine::new();
+    engine.add_force_field(Spring { stiffness: 0.01 });
+    engine.add_force_field(ClosureField::new("Wind", |_, _| (0.0, 0.1, 0.0)));
+    let mut particles = lone_planet(100.0, 0.0);
+
+    engine.update_physics(&mut particles, 1.0);
+
+    // The spring pulls back towards the origin, the wind pushes along y
+    assert!(particles.vx[0] < 0.0);
+    assert!(particles.vy[0] > 0.0);
+    assert_eq!(engine.force_fields.len(), 2);
+}
+
+#[test]
+fn fields_can_be_removed() {
+    let mut engine = PhysicsEngine::new();
+    engine.add_force_field(Spring { stiffness: 0.01 });
+    engine.add_force_field(UniformField { acceleration: (1.0, 0.0, 0.0) });
+
+    assert!(engine.remove_force_field("Spring"));
+    assert!(!engine.remove_force_field("Spring"));
+    assert_eq!(engine.force_fields.len(), 1);
+
+    engine.clear_force_fields();
+    let mut particles = lone_planet(100.0, 0.0);
+    engine.update_physics(&mut particles, 1.0);
+    assert_eq!((particles.vx[0], particles.vy[0]), (0.0, 0.0));
+}